@@ -1,8 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::OnceLock;
+
+use crate::span::Span;
 
 type StateIndex = usize;
 type Symbol = String;
 
+// Bumped whenever the fingerprint encoding changes.
+const FINGERPRINT_VERSION: u64 = 1;
+
 #[derive(Copy, Clone)]
 pub struct State {
     pub number: StateIndex,
@@ -18,17 +24,27 @@ impl State {
             is_error,
         }
     }
+
+    pub fn is_accepting(&self) -> bool {
+        self.is_final & !self.is_error
+    }
 }
 
 pub struct TransitionMatrix {
     matrix: Vec<HashMap<Symbol, State>>,
+    states: Vec<Option<State>>,
     start_state: State,
 }
 
 impl TransitionMatrix {
     pub fn new() -> TransitionMatrix {
+        TransitionMatrix::with_capacity(0)
+    }
+
+    pub fn with_capacity(states: usize) -> TransitionMatrix {
         TransitionMatrix {
-            matrix: Vec::new(),
+            matrix: Vec::with_capacity(states),
+            states: Vec::with_capacity(states),
             start_state: State::new(0, false, false),
         }
     }
@@ -37,6 +53,11 @@ impl TransitionMatrix {
         &self.start_state
     }
 
+    pub fn set_start_state(&mut self, state: State) {
+        self.start_state = state;
+        self.record(state);
+    }
+
     pub fn transition(&self, state: &State, symbol: &str) -> Option<&State> {
         if state.number >= self.matrix.len() {
             return None;
@@ -48,26 +69,306 @@ impl TransitionMatrix {
         }
     }
 
+    pub fn target(&self, number: StateIndex, symbol: &str) -> Option<&State> {
+        self.matrix.get(number)?.get(symbol)
+    }
+
+    pub fn state(&self, number: StateIndex) -> Option<&State> {
+        if self.start_state.number == number {
+            return Some(&self.start_state);
+        }
+
+        self.states.get(number)?.as_ref()
+    }
+
+    pub fn symbols(&self) -> HashSet<&str> {
+        self.matrix
+            .iter()
+            .flat_map(|row| row.keys())
+            .map(|symbol| symbol.as_str())
+            .collect()
+    }
+
+    pub fn transitions_from(
+        &self,
+        number: StateIndex,
+    ) -> impl Iterator<Item = (&str, &State)> {
+        self.matrix
+            .get(number)
+            .into_iter()
+            .flatten()
+            .map(|(symbol, state)| (symbol.as_str(), state))
+    }
+
+    pub fn transitions(
+        &self,
+    ) -> impl Iterator<Item = (StateIndex, &str, &State)> {
+        self.matrix.iter().enumerate().flat_map(|(number, row)| {
+            row.iter()
+                .map(move |(symbol, state)| (number, symbol.as_str(), state))
+        })
+    }
+
     pub fn add(&mut self, from_state: State, to_state: State, symbol: &str) {
+        self.record(from_state);
+        self.record(to_state);
+
         if from_state.number >= self.matrix.len() {
             self.matrix.resize(from_state.number + 1, HashMap::new())
         }
-        self.matrix
+        let previous = self
+            .matrix
             .get_mut(from_state.number)
             .unwrap()
             .insert(symbol.to_string(), to_state);
+        debug_assert!(
+            previous.is_none_or(|previous| previous.number == to_state.number),
+            "state {} already has a transition on {:?}",
+            from_state.number,
+            symbol
+        );
     }
+
+    // Keeps the flags of each state number for constant-time lookup in
+    // `state`. They are copied onto every edge as well, so all mentions of
+    // a number must agree on them.
+    fn record(&mut self, state: State) {
+        if state.number >= self.states.len() {
+            self.states.resize(state.number + 1, None);
+        }
+        let entry = &mut self.states[state.number];
+        debug_assert!(
+            entry.is_none_or(|recorded| {
+                recorded.is_final == state.is_final
+                    && recorded.is_error == state.is_error
+            }),
+            "state {} was added with conflicting flags",
+            state.number
+        );
+        *entry = Some(state);
+    }
+}
+
+impl Default for TransitionMatrix {
+    fn default() -> TransitionMatrix {
+        TransitionMatrix::new()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    InvalidLine(usize),
+    NondeterministicTransition(usize),
+}
+
+/// The bounds of a char range are out of order.
+#[derive(Debug, PartialEq)]
+pub struct EmptyRange {
+    pub lo: char,
+    pub hi: char,
 }
 
 pub struct Automaton {
     transition_matrix: TransitionMatrix,
+    // Computed on the first search and reset whenever a transition is added.
+    prefix: OnceLock<String>,
 }
 
 impl Automaton {
     pub fn new() -> Automaton {
+        Automaton::with_capacity(0)
+    }
+
+    pub fn with_capacity(states: usize) -> Automaton {
         Automaton {
-            transition_matrix: TransitionMatrix::new(),
+            transition_matrix: TransitionMatrix::with_capacity(states),
+            prefix: OnceLock::new(),
+        }
+    }
+
+    /// Parses one transition `<from> <symbol> <to>` or directive
+    /// (`start <n>`, `final <n>...`, `error <n>...`) per line; blank lines
+    /// and lines starting with `#` are skipped. Errors carry the 1-based line
+    /// number.
+    pub fn from_transition_list(text: &str) -> Result<Automaton, ParseError> {
+        let mut start = 0;
+        let mut finals = HashSet::new();
+        let mut errors = HashSet::new();
+        let mut transitions = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let parse = |token: &str| {
+                token
+                    .parse::<StateIndex>()
+                    .map_err(|_| ParseError::InvalidLine(line_number))
+            };
+
+            match tokens.as_slice() {
+                [] => {}
+                [first, ..] if first.starts_with('#') => {}
+                ["start", number] => start = parse(number)?,
+                ["final", numbers @ ..] if !numbers.is_empty() => {
+                    for number in numbers {
+                        finals.insert(parse(number)?);
+                    }
+                }
+                ["error", numbers @ ..] if !numbers.is_empty() => {
+                    for number in numbers {
+                        errors.insert(parse(number)?);
+                    }
+                }
+                [from_state, symbol, to_state] => transitions.push((
+                    line_number,
+                    parse(from_state)?,
+                    *symbol,
+                    parse(to_state)?,
+                )),
+                _ => return Err(ParseError::InvalidLine(line_number)),
+            }
+        }
+
+        let state = |number: StateIndex| {
+            State::new(
+                number,
+                finals.contains(&number),
+                errors.contains(&number),
+            )
+        };
+        let mut new = Automaton::new();
+        new.transition_matrix.set_start_state(state(start));
+
+        for (line_number, from_state, symbol, to_state) in transitions {
+            let existing = new
+                .transition_matrix
+                .transition(&state(from_state), symbol)
+                .map(|state| state.number);
+            if existing.is_some_and(|existing| existing != to_state) {
+                return Err(ParseError::NondeterministicTransition(
+                    line_number,
+                ));
+            }
+
+            new.add_transition(state(from_state), state(to_state), symbol);
+        }
+
+        Ok(new)
+    }
+
+    /// Builds the minimal DFA accepting exactly `keywords`, sharing both
+    /// their common prefixes and their common suffixes.
+    pub fn from_keywords(keywords: &[&str]) -> Automaton {
+        // A trie of the keywords; children are always created after their
+        // parent.
+        let mut trie: Vec<(bool, BTreeMap<char, usize>)> =
+            vec![(false, BTreeMap::new())];
+        for keyword in keywords {
+            let mut node = 0;
+            for symbol in keyword.chars() {
+                node = match trie[node].1.get(&symbol) {
+                    Some(&child) => child,
+                    None => {
+                        let child = trie.len();
+                        trie.push((false, BTreeMap::new()));
+                        trie[node].1.insert(symbol, child);
+                        child
+                    }
+                };
+            }
+            trie[node].0 = true;
+        }
+
+        // Visiting children first, nodes with the same finality and the same
+        // merged children accept the same suffixes and are merged.
+        let mut classes: HashMap<(bool, Vec<(char, StateIndex)>), StateIndex> =
+            HashMap::new();
+        let mut class_of = vec![0; trie.len()];
+        for node in (0..trie.len()).rev() {
+            let (is_final, children) = &trie[node];
+            let key = (
+                *is_final,
+                children
+                    .iter()
+                    .map(|(&symbol, &child)| (symbol, class_of[child]))
+                    .collect(),
+            );
+            let count = classes.len();
+            class_of[node] = *classes.entry(key).or_insert(count);
+        }
+
+        // The root is the last class created; number it as state 0.
+        let state = |node: usize| {
+            State::new(classes.len() - 1 - class_of[node], trie[node].0, false)
+        };
+        let mut new = Automaton::with_capacity(classes.len());
+        for (node, (_, children)) in trie.iter().enumerate() {
+            for (symbol, &child) in children {
+                new.add_transition(
+                    state(node),
+                    state(child),
+                    &symbol.to_string(),
+                );
+            }
+        }
+        new.transition_matrix.set_start_state(state(0));
+
+        new
+    }
+
+    /// Builds the DFA accepting `open`, then any string over `alphabet`
+    /// without `close` in it, then `close`; i.e. a reluctant match of a
+    /// delimited block such as a `/* ... */` comment.
+    pub fn until(open: &str, close: &str, alphabet: &[char]) -> Automaton {
+        let open: Vec<char> = open.chars().collect();
+        let close: Vec<char> = close.chars().collect();
+        let mut symbols: Vec<char> = alphabet.to_vec();
+        symbols.extend(&close);
+        symbols.sort();
+        symbols.dedup();
+
+        // States `0..open.len()` read `open`; state `open.len() + k` has
+        // matched the first `k` chars of `close`.
+        let body = open.len();
+        let end = body + close.len();
+        let state =
+            |number: StateIndex| State::new(number, number == end, false);
+
+        let mut new = Automaton::with_capacity(end + 1);
+        for (number, symbol) in open.iter().enumerate() {
+            new.add_transition(
+                state(number),
+                state(number + 1),
+                &symbol.to_string(),
+            );
+        }
+        for matched in 0..close.len() {
+            for &symbol in &symbols {
+                let mut read = close[..matched].to_vec();
+                read.push(symbol);
+                // The longest prefix of `close` that ends the chars read.
+                let next = (0..=read.len().min(close.len()))
+                    .rev()
+                    .find(|&length| read.ends_with(&close[..length]))
+                    .unwrap();
+
+                new.add_transition(
+                    state(body + matched),
+                    state(body + next),
+                    &symbol.to_string(),
+                );
+            }
         }
+        new.transition_matrix.set_start_state(state(0));
+
+        new
+    }
+
+    /// Sets the start state along with its flags, e.g. a final start state
+    /// makes the automaton accept the empty string.
+    pub fn set_start_state(&mut self, state: State) {
+        self.prefix.take();
+        self.transition_matrix.set_start_state(state);
     }
 
     pub fn add_transition(
@@ -76,226 +377,4104 @@ impl Automaton {
         to_state: State,
         symbol: &str,
     ) {
+        self.prefix.take();
         self.transition_matrix.add(from_state, to_state, symbol);
     }
 
-    pub fn consume(&self, sequence: &str) -> bool {
+    pub fn add_set_transition(
+        &mut self,
+        from_state: State,
+        to_state: State,
+        symbols: &[char],
+    ) {
+        for symbol in symbols {
+            self.add_transition(from_state, to_state, &symbol.to_string());
+        }
+    }
+
+    /// Adds a transition on every char in `lo..=hi`, e.g. `'a'..='z'`.
+    /// This is shorthand for `add_set_transition`: each char still gets its
+    /// own entry in the matrix, so it is meant for small ranges rather than
+    /// large spans of Unicode.
+    pub fn add_range_transition(
+        &mut self,
+        from_state: State,
+        to_state: State,
+        lo: char,
+        hi: char,
+    ) -> Result<(), EmptyRange> {
+        if lo > hi {
+            return Err(EmptyRange { lo, hi });
+        }
+        let symbols: Vec<char> = (lo..=hi).collect();
+
+        self.add_set_transition(from_state, to_state, &symbols);
+
+        Ok(())
+    }
+
+    /// Matching borrows `sequence` as a `&str` and never copies it or
+    /// allocates per symbol, whether it is passed as `&str`, `String` or
+    /// `Cow<str>`.
+    pub fn consume(&self, sequence: impl AsRef<str>) -> bool {
+        self.consume_with_observer(sequence, &mut |_, _, _| {})
+    }
+
+    pub fn consume_with_observer(
+        &self,
+        sequence: impl AsRef<str>,
+        observer: &mut impl FnMut(&State, char, &State),
+    ) -> bool {
+        self.run(
+            self.transition_matrix.start_state(),
+            sequence.as_ref(),
+            observer,
+        )
+    }
+
+    /// Returns the state numbers visited while consuming `sequence`,
+    /// starting with the start state, if the sequence is accepted.
+    pub fn consume_path(
+        &self,
+        sequence: impl AsRef<str>,
+    ) -> Option<Vec<StateIndex>> {
+        let mut path = vec![self.transition_matrix.start_state().number];
+
+        self.consume_with_observer(sequence, &mut |_, _, to_state| {
+            path.push(to_state.number)
+        })
+        .then_some(path)
+    }
+
+    /// Consumes `input` with each element mapped to a transition symbol by
+    /// `map`, e.g. to match over character categories instead of characters.
+    pub fn consume_mapped<I, F, S>(&self, input: I, mut map: F) -> bool
+    where
+        I: IntoIterator,
+        F: FnMut(I::Item) -> S,
+        S: AsRef<str>,
+    {
         let mut current_state = self.transition_matrix.start_state();
 
+        for element in input {
+            match self
+                .transition_matrix
+                .transition(current_state, map(element).as_ref())
+            {
+                None => return false,
+                Some(state) => current_state = state,
+            }
+        }
+
+        current_state.is_accepting()
+    }
+
+    /// Runs the automaton from `start` instead of the designated start
+    /// state, so that a shared automaton can serve the sub-language rooted at
+    /// any of its states.
+    pub fn consume_from(
+        &self,
+        start: &State,
+        sequence: impl AsRef<str>,
+    ) -> bool {
+        let start = self.transition_matrix.state(start.number).unwrap_or(start);
+
+        self.run(start, sequence.as_ref(), &mut |_, _, _| {})
+    }
+
+    fn run(
+        &self,
+        start: &State,
+        sequence: &str,
+        observer: &mut impl FnMut(&State, char, &State),
+    ) -> bool {
+        let mut current_state = start;
+
         for symbol in sequence.chars() {
             match self.transition(current_state, symbol) {
                 None => {
                     return false;
                 }
                 Some(state) => {
+                    observer(current_state, symbol, state);
                     current_state = state;
                 }
             }
         }
 
-        return current_state.is_final & !current_state.is_error;
+        current_state.is_accepting()
     }
 
-    fn transition(&self, state: &State, symbol: char) -> Option<&State> {
-        self.transition_matrix
-            .transition(state, symbol.to_string().as_str())
+    /// Returns the span of the leftmost-longest match in `haystack`.
+    pub fn find(&self, haystack: impl AsRef<str>) -> Option<Span> {
+        self.find_at(haystack.as_ref(), 0, self.search_prefix())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    fn create_automaton() -> Automaton {
-        let start = State::new(0, false, false);
-        let first = State::new(1, false, false);
-        let second = State::new(2, true, false);
+    /// Returns the successive non-overlapping leftmost-longest matches in
+    /// `haystack`. An empty match is reported at most once per position, and
+    /// the search resumes one char past it, so patterns such as `a*` cannot
+    /// get stuck.
+    pub fn find_iter<'h>(
+        &'h self,
+        haystack: &'h str,
+    ) -> impl Iterator<Item = Span> + 'h {
+        let prefix = self.search_prefix();
+        let mut from = Some(0);
 
-        let mut automaton = Automaton::new();
-        automaton.add_transition(start, first, "a");
-        automaton.add_transition(first, second, "b");
+        std::iter::from_fn(move || {
+            let span = self.find_at(haystack, from?, prefix)?;
+            from = match span.is_empty() {
+                false => Some(span.end),
+                true => haystack[span.end..]
+                    .chars()
+                    .next()
+                    .map(|symbol| span.end + symbol.len_utf8()),
+            };
 
-        return automaton;
+            Some(span)
+        })
     }
 
-    #[test]
-    fn test_consume_ab() {
-        let automaton = create_automaton();
-        assert_eq!(automaton.consume("ab"), true);
-    }
+    fn find_at(
+        &self,
+        haystack: &str,
+        mut from: usize,
+        prefix: &str,
+    ) -> Option<Span> {
+        // Matches can only start where the literal prefix occurs; the empty
+        // prefix occurs at every position.
+        while let Some(offset) = haystack[from..].find(prefix) {
+            let start = from + offset;
+            if let Some(end) = self.longest_match(haystack, start) {
+                return Some(Span::new(start, end));
+            }
 
-    #[test]
-    fn test_consume_abc() {
-        let automaton = create_automaton();
-        assert_eq!(automaton.consume("abc"), false);
+            match haystack[start..].chars().next() {
+                None => break,
+                Some(symbol) => from = start + symbol.len_utf8(),
+            }
+        }
+
+        None
     }
 
-    #[test]
-    fn test_consume_cab() {
-        let automaton = create_automaton();
-        assert_eq!(automaton.consume("cab"), false);
+    fn search_prefix(&self) -> &str {
+        self.prefix
+            .get_or_init(|| self.literal_prefix().unwrap_or_default())
     }
-}
 
-mod nfa {
-    use std::collections::HashSet;
+    /// Returns the longest literal string that every accepted string starts
+    /// with, if any.
+    pub fn literal_prefix(&self) -> Option<String> {
+        let live = self.live_states();
+        let mut state = self.transition_matrix.start_state().number;
+        let mut visited = HashSet::from([state]);
+        let mut prefix = String::new();
 
-    const EPSILON: &str = "ε";
+        while live.contains(&state) && !self.accepts_index(Some(state)) {
+            let mut outgoing = self
+                .transition_matrix
+                .transitions_from(state)
+                .filter(|(_, target)| live.contains(&target.number));
+            let (symbol, target) = match (outgoing.next(), outgoing.next()) {
+                (Some(edge), None) => edge,
+                _ => break,
+            };
+            if !visited.insert(target.number) {
+                break;
+            }
 
-    pub struct Automaton {
-        pub regex_str: String,
-        pub start_state: State,
-        transition_matrix: TransitionMatrix,
+            prefix.push_str(symbol);
+            state = target.number;
+        }
+
+        if prefix.is_empty() {
+            None
+        } else {
+            Some(prefix)
+        }
     }
 
-    impl Automaton {
-        pub fn from_regex(regex_str: &str) -> Automaton {
-            if regex_str.len() == 1 {
-                return Automaton::from_char(regex_str);
+    /// Merges the transitions of all symbols within each of `classes`, so
+    /// that the symbols of a class become interchangeable. Symbols that are
+    /// not in any class are kept as singleton classes.
+    pub fn quotient_symbols(&self, classes: &[Vec<char>]) -> Automaton {
+        let mut groups: Vec<Vec<String>> = classes
+            .iter()
+            .map(|class| {
+                class.iter().map(|symbol| symbol.to_string()).collect()
+            })
+            .collect();
+        let mut symbols: Vec<&str> =
+            self.transition_matrix.symbols().into_iter().collect();
+        symbols.sort();
+        for symbol in symbols {
+            if !groups.iter().flatten().any(|member| member == symbol) {
+                groups.push(vec![symbol.to_string()]);
             }
+        }
+
+        let start =
+            BTreeSet::from([self.transition_matrix.start_state().number]);
+        let mut subsets = vec![start.clone()];
+        let mut numbers = HashMap::from([(start.clone(), 0)]);
+        let mut new = Automaton::new();
+        new.transition_matrix
+            .set_start_state(self.subset_state(0, &start));
+
+        let mut number = 0;
+        while number < subsets.len() {
+            let from_state = self.subset_state(number, &subsets[number]);
 
-            let (first_char, rest) = regex_str.split_at(1);
+            for group in groups.iter() {
+                let targets: BTreeSet<StateIndex> = subsets[number]
+                    .iter()
+                    .filter_map(|&state| self.transition_matrix.state(state))
+                    .flat_map(|state| {
+                        group.iter().filter_map(|symbol| {
+                            self.transition_matrix.transition(state, symbol)
+                        })
+                    })
+                    .map(|state| state.number)
+                    .collect();
+                if targets.is_empty() {
+                    continue;
+                }
+
+                let target_number =
+                    *numbers.entry(targets.clone()).or_insert_with(|| {
+                        subsets.push(targets.clone());
+                        subsets.len() - 1
+                    });
+                let to_state = self.subset_state(target_number, &targets);
+
+                for symbol in group.iter() {
+                    new.add_transition(from_state, to_state, symbol);
+                }
+            }
 
-            Automaton::from_char(first_char).concatenate(rest)
+            number += 1;
         }
 
-        pub fn from_char(character: &str) -> Automaton {
-            let start = State::new(0, false);
-            let end = State::new(1, true);
+        new
+    }
 
-            let new = Automaton {
-                regex_str: character.to_string(),
-                start_state: start,
-                transition_matrix: TransitionMatrix::new(),
+    /// Restricts the language to strings whose length lies in `[min, max]`,
+    /// with no upper bound when `max` is `None`.
+    pub fn with_length_bounds(
+        &self,
+        min: usize,
+        max: Option<usize>,
+    ) -> Automaton {
+        let in_bounds = |length: usize| {
+            length >= min && max.is_none_or(|max| length <= max)
+        };
+        let product_state =
+            |number: StateIndex, state: &State, length: usize| {
+                State::new(
+                    number,
+                    state.is_accepting() && in_bounds(length),
+                    state.is_error,
+                )
             };
 
-            new.add_transition(&new.start_state, &end, character);
+        let start = *self.transition_matrix.start_state();
+        let mut pairs = vec![(start.number, 0)];
+        let mut numbers = HashMap::from([((start.number, 0), 0)]);
+        let mut new = Automaton::new();
+        new.transition_matrix
+            .set_start_state(product_state(0, &start, 0));
 
-            return new;
-        }
+        let mut number = 0;
+        while number < pairs.len() {
+            let (state, length) = pairs[number];
+            let from_state = product_state(
+                number,
+                self.transition_matrix.state(state).unwrap(),
+                length,
+            );
 
-        pub fn concatenate(&self, regex_str: &str) -> Automaton {
-            let other = Automaton::from_regex(regex_str);
-            let mut new = self.append(other);
+            // Lengths beyond `min` are indistinguishable when unbounded, so
+            // the counter saturates there.
+            let next_length = match max {
+                Some(max) if length >= max => None,
+                Some(_) => Some(length + 1),
+                None => Some((length + 1).min(min)),
+            };
+            if let Some(next_length) = next_length {
+                for (symbol, target) in
+                    self.transition_matrix.transitions_from(state)
+                {
+                    let pair = (target.number, next_length);
+                    let target_number =
+                        *numbers.entry(pair).or_insert_with(|| {
+                            pairs.push(pair);
+                            pairs.len() - 1
+                        });
+                    let to_state =
+                        product_state(target_number, target, next_length);
 
-            new.append_final();
-            new.insert_start();
-            new.regex_str = self.regex_str.as_str().to_string() + regex_str;
+                    new.add_transition(from_state, to_state, symbol);
+                }
+            }
 
-            new
+            number += 1;
         }
 
-        pub fn union(&self, regex_str: &str) -> Automaton {
-            let other = Automaton::from_regex(regex_str);
-            let mut new = self.add(other);
+        new
+    }
 
-            new.append_final();
-            new.insert_start();
-            new.regex_str =
-                self.regex_str.as_str().to_string() + "|" + regex_str;
+    /// Returns an automaton for the Kleene closure of the language, built
+    /// from the existing transitions and determinized on the fly.
+    pub fn star(&self) -> Automaton {
+        let start = self.transition_matrix.start_state().number;
+        let mut symbols: Vec<&str> =
+            self.transition_matrix.symbols().into_iter().collect();
+        symbols.sort();
 
-            new
-        }
+        // The fresh start state accepts the empty string; it is kept apart
+        // from any later subset containing the old start, which may not.
+        let initial = State::new(0, true, false);
+        let mut subsets = vec![BTreeSet::from([start])];
+        let mut numbers = HashMap::new();
+        let mut new = Automaton::new();
+        new.transition_matrix.set_start_state(initial);
 
-        pub fn kleene_closure(&self) -> Automaton {
-            let mut new = Automaton::from_regex(self.regex_str.as_str());
+        let mut number = 0;
+        while number < subsets.len() {
+            let from_state = match number {
+                0 => initial,
+                _ => self.subset_state(number, &subsets[number]),
+            };
 
-            for end_state in new.end_states() {
-                new.add_transition(&new.start_state, end_state, EPSILON);
+            for symbol in symbols.iter() {
+                let mut targets: BTreeSet<StateIndex> = subsets[number]
+                    .iter()
+                    .filter_map(|&state| {
+                        self.transition_matrix.target(state, symbol)
+                    })
+                    .map(|state| state.number)
+                    .collect();
+                if targets.is_empty() {
+                    continue;
+                }
+                // Reaching acceptance may also start the next repetition.
+                if targets.iter().any(|&state| self.accepts_index(Some(state)))
+                {
+                    targets.insert(start);
+                }
+
+                let target_number =
+                    *numbers.entry(targets.clone()).or_insert_with(|| {
+                        subsets.push(targets.clone());
+                        subsets.len() - 1
+                    });
+                let to_state = self.subset_state(target_number, &targets);
+
+                new.add_transition(from_state, to_state, symbol);
             }
 
-            new.append_final();
-            new.insert_start();
+            number += 1;
+        }
+
+        new
+    }
+
+    /// Returns an automaton for the concatenation of the two languages. Each
+    /// new state pairs the state reached in `self`, if any, with the states
+    /// of `other` entered whenever `self` accepted a prefix.
+    pub fn concat(&self, other: &Automaton) -> Automaton {
+        let other_start = other.transition_matrix.start_state().number;
+        let mut symbols: Vec<&str> = self
+            .transition_matrix
+            .symbols()
+            .union(&other.transition_matrix.symbols())
+            .copied()
+            .collect();
+        symbols.sort();
 
-            for end_state in new.end_states() {
-                new.add_transition(&new.start_state, end_state, EPSILON);
+        let bridge = |state: Option<StateIndex>,
+                      mut others: BTreeSet<StateIndex>| {
+            if self.accepts_index(state) {
+                others.insert(other_start);
             }
+            (state, others)
+        };
+        let subset_state =
+            |number: StateIndex, others: &BTreeSet<StateIndex>| {
+                let is_final = others
+                    .iter()
+                    .any(|&state| other.accepts_index(Some(state)));
+                State::new(number, is_final, false)
+            };
 
-            new.regex_str = self.regex_str + "*";
+        let start = self.transition_matrix.start_state().number;
+        let mut subsets = vec![bridge(Some(start), BTreeSet::new())];
+        let mut numbers = HashMap::from([(subsets[0].clone(), 0)]);
+        let mut new = Automaton::new();
+        new.transition_matrix
+            .set_start_state(subset_state(0, &subsets[0].1));
 
-            new
-        }
+        let mut number = 0;
+        while number < subsets.len() {
+            let (state, others) = subsets[number].clone();
+            let from_state = subset_state(number, &others);
 
-        pub fn add_transition(
-            &mut self,
-            from_state: &State,
-            to_state: &State,
-            symbol: &str,
-        ) {
-            self.transition_matrix
-                .add_transition(from_state, to_state, symbol);
+            for symbol in symbols.iter() {
+                let next_state = state
+                    .and_then(|state| {
+                        self.transition_matrix.target(state, symbol)
+                    })
+                    .map(|state| state.number);
+                let next_others = others
+                    .iter()
+                    .filter_map(|&state| {
+                        other.transition_matrix.target(state, symbol)
+                    })
+                    .map(|state| state.number)
+                    .collect();
+                let target = bridge(next_state, next_others);
+                if target.0.is_none() && target.1.is_empty() {
+                    continue;
+                }
+
+                let target_number =
+                    *numbers.entry(target.clone()).or_insert_with(|| {
+                        subsets.push(target.clone());
+                        subsets.len() - 1
+                    });
+                let to_state = subset_state(target_number, &target.1);
+
+                new.add_transition(from_state, to_state, symbol);
+            }
+
+            number += 1;
         }
 
-        pub fn transitions(&self) -> Iter<Transition> {}
+        new
+    }
 
-        fn append(&self, other: Automaton) -> Automaton {}
+    /// Returns an automaton for the intersection of all the languages, over
+    /// `alphabet`, built as a single product whose states track one state
+    /// of every automaton. A product state is an error state as soon as any
+    /// of its components is, so error states are never folded into plain
+    /// rejecting ones.
+    pub fn intersect_all(
+        automata: &[&Automaton],
+        alphabet: &[char],
+    ) -> Automaton {
+        // Whether each state of each automaton accepts and whether it is
+        // an error state, by state number.
+        let flags: Vec<Vec<(bool, bool)>> = automata
+            .iter()
+            .map(|automaton| {
+                let matrix = &automaton.transition_matrix;
+                let count =
+                    matrix.states.len().max(matrix.start_state().number + 1);
+                (0..count)
+                    .map(|number| {
+                        matrix.state(number).map_or((false, false), |state| {
+                            (state.is_accepting(), state.is_error)
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        let tuple_state = |number: StateIndex, tuple: &[StateIndex]| {
+            let is_final = flags
+                .iter()
+                .zip(tuple)
+                .all(|(flags, &state)| flags[state].0);
+            let is_error = flags
+                .iter()
+                .zip(tuple)
+                .any(|(flags, &state)| flags[state].1);
+            State::new(number, is_final, is_error)
+        };
 
-        fn add(&self, other: Automaton) -> Automaton {}
+        let start: Vec<StateIndex> = automata
+            .iter()
+            .map(|automaton| automaton.transition_matrix.start_state().number)
+            .collect();
+        let mut tuples = vec![start.clone()];
+        let mut numbers = HashMap::from([(start, 0)]);
+        let mut new = Automaton::new();
+        new.transition_matrix
+            .set_start_state(tuple_state(0, &tuples[0]));
 
-        fn append_final(&mut self) {}
+        let mut number = 0;
+        while number < tuples.len() {
+            let from_state = tuple_state(number, &tuples[number]);
 
-        fn insert_start(&mut self) {}
+            for &symbol in alphabet {
+                // Stuck in any automaton means stuck in the intersection.
+                let targets: Option<Vec<StateIndex>> = automata
+                    .iter()
+                    .zip(&tuples[number])
+                    .map(|(automaton, &state)| {
+                        automaton.transition_index(Some(state), symbol)
+                    })
+                    .collect();
+                let targets = match targets {
+                    None => continue,
+                    Some(targets) => targets,
+                };
 
-        fn end_states(&self) -> Iter<&State> {}
-    }
+                let target_number =
+                    *numbers.entry(targets.clone()).or_insert_with(|| {
+                        tuples.push(targets.clone());
+                        tuples.len() - 1
+                    });
+                let to_state = tuple_state(target_number, &targets);
 
-    pub struct State {
-        number: usize,
-        name: String,
-        is_final: bool,
+                new.add_transition(from_state, to_state, &symbol.to_string());
+            }
+
+            number += 1;
+        }
+
+        new
     }
 
-    impl State {
-        pub fn new(number: usize, is_final: bool) -> State {
-            State {
-                number,
-                name: "s".to_string() + number.to_string().as_str(),
-                is_final,
+    /// Returns a shortest string reaching each reachable accepting state.
+    pub fn witnesses(&self) -> HashMap<StateIndex, String> {
+        let start = self.transition_matrix.start_state().number;
+        let mut paths = HashMap::from([(start, String::new())]);
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(state) = queue.pop_front() {
+            let mut transitions: Vec<(&str, &State)> =
+                self.transition_matrix.transitions_from(state).collect();
+            transitions.sort_by_key(|&(symbol, _)| symbol);
+
+            for (symbol, to_state) in transitions {
+                if !paths.contains_key(&to_state.number) {
+                    let path = paths[&state].clone() + symbol;
+                    paths.insert(to_state.number, path);
+                    queue.push_back(to_state.number);
+                }
             }
         }
-    }
 
-    pub struct Transition<'a> {
-        from_state: &'a State,
-        to_state: &'a State,
-        symbol: String,
+        paths.retain(|&state, _| self.accepts_index(Some(state)));
+
+        paths
     }
 
-    impl<'a> Transition<'a> {
-        pub fn new(
-            from_state: &'a State,
-            to_state: &'a State,
-            symbol: &str,
-        ) -> Transition<'a> {
-            Transition {
-                from_state,
-                to_state,
-                symbol: symbol.to_string(),
+    /// Returns the symbols that, appended to `prefix`, keep it on a path to
+    /// some accepted string; empty if `prefix` is already stuck.
+    pub fn suggestions(&self, prefix: impl AsRef<str>) -> Vec<char> {
+        let mut current_state = self.transition_matrix.start_state();
+        for symbol in prefix.as_ref().chars() {
+            match self.transition(current_state, symbol) {
+                None => return Vec::new(),
+                Some(state) => current_state = state,
             }
         }
 
-        pub fn to_str(&self) -> String {
-            format!(
-                "({}->{},{})",
-                &self.from_state.name, &self.to_state.name, &self.symbol
-            )
-        }
+        let live = self.live_states();
+        let mut suggestions: Vec<char> = self
+            .transition_matrix
+            .transitions_from(current_state.number)
+            .filter(|(_, target)| live.contains(&target.number))
+            .filter_map(|(symbol, _)| {
+                let mut chars = symbol.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(symbol), None) => Some(symbol),
+                    _ => None,
+                }
+            })
+            .collect();
+        suggestions.sort();
 
-        pub fn from_str(transition_str: &str) -> Transition {}
+        suggestions
     }
 
-    type TransitionHash = String;
-
-    pub struct TransitionMatrix {
-        matrix: HashSet<TransitionHash>,
+    /// Returns a dense `states × alphabet` table of transition targets, with
+    /// a row per state number.
+    pub fn transition_table(
+        &self,
+        alphabet: &[char],
+    ) -> Vec<Vec<Option<StateIndex>>> {
+        (0..self.state_count())
+            .map(|number| {
+                alphabet
+                    .iter()
+                    .map(|&symbol| self.transition_index(Some(number), symbol))
+                    .collect()
+            })
+            .collect()
     }
 
-    impl TransitionMatrix {
-        pub fn new() -> TransitionMatrix {
-            TransitionMatrix {
-                matrix: HashSet::new(),
-            }
-        }
+    /// Completes the automaton over `alphabet` with a dedicated sink state,
+    /// so that matching never meets a missing transition.
+    pub fn into_complete_with_sink(self, alphabet: &[char]) -> CompleteDfa {
+        let sink = self.state_count();
+        let table = self
+            .transition_table(alphabet)
+            .into_iter()
+            .chain([vec![None; alphabet.len()]])
+            .map(|row| {
+                row.into_iter()
+                    .map(|target| target.unwrap_or(sink))
+                    .collect()
+            })
+            .collect();
+        let accepting = (0..sink)
+            .map(|number| self.accepts_index(Some(number)))
+            .chain([false])
+            .collect();
+
+        CompleteDfa {
+            table,
+            columns: alphabet
+                .iter()
+                .enumerate()
+                .map(|(column, &symbol)| (symbol, column))
+                .collect(),
+            accepting,
+            start: self.transition_matrix.start_state().number,
+            sink,
+        }
+    }
+
+    /// Like `into_complete_with_sink`, but over the symbols of `interner`
+    /// after interning the automaton's own single-char symbols. Automata
+    /// completed against the same interner share their column numbering.
+    pub fn into_complete_interned(
+        self,
+        interner: &mut SymbolInterner,
+    ) -> CompleteDfa {
+        let mut symbols: Vec<char> = self
+            .transition_matrix
+            .symbols()
+            .into_iter()
+            .filter_map(|symbol| {
+                let mut chars = symbol.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(symbol), None) => Some(symbol),
+                    _ => None,
+                }
+            })
+            .collect();
+        symbols.sort();
+        for symbol in symbols {
+            interner.intern(symbol);
+        }
+
+        self.into_complete_with_sink(interner.symbols())
+    }
+
+    /// Returns literal substrings that every accepted string contains, in the
+    /// order they occur. A literal is a maximal run of transitions that all
+    /// accepting paths take back to back.
+    pub fn required_literals(&self) -> Vec<String> {
+        let edges = self.useful_edges();
+        let start = self.transition_matrix.start_state().number;
+
+        // An edge is required if acceptance is unreachable without it.
+        let required: Vec<&(StateIndex, &str, StateIndex)> = edges
+            .iter()
+            .filter(|&edge| {
+                let mut reached = HashSet::from([start]);
+                let mut stack = vec![start];
+
+                while let Some(state) = stack.pop() {
+                    if self.accepts_index(Some(state)) {
+                        return false;
+                    }
+                    for other in edges.iter() {
+                        if other != edge
+                            && other.0 == state
+                            && reached.insert(other.2)
+                        {
+                            stack.push(other.2);
+                        }
+                    }
+                }
+
+                true
+            })
+            .collect();
+
+        // A required edge continues the literal of the one before it when
+        // the state between them has no other way forward.
+        let out_degree = |state: StateIndex| {
+            edges.iter().filter(|edge| edge.0 == state).count()
+        };
+        let next = |edge: &(StateIndex, &str, StateIndex)| {
+            required
+                .iter()
+                .find(|other| other.0 == edge.2 && out_degree(edge.2) == 1)
+                .copied()
+        };
+
+        let mut literals: Vec<(usize, String)> = required
+            .iter()
+            .filter(|&&edge| {
+                !required.iter().any(|&other| next(other) == Some(edge))
+            })
+            .map(|&edge| {
+                let mut literal = edge.1.to_string();
+                let mut current = edge;
+                while let Some(following) = next(current) {
+                    literal.push_str(following.1);
+                    current = following;
+                }
+
+                (self.distance(edge.0), literal)
+            })
+            .collect();
+        literals.sort();
+
+        literals.into_iter().map(|(_, literal)| literal).collect()
+    }
+
+    /// Returns a digest of the automaton that is stable across runs and
+    /// builds. States are numbered in breadth-first order from the start,
+    /// visiting symbols in sorted order, so the digest does not depend on
+    /// the original state numbers.
+    pub fn fingerprint(&self) -> u64 {
+        let start = self.transition_matrix.start_state();
+        let mut numbers = HashMap::from([(start.number, 0)]);
+        let mut queue = VecDeque::from([*start]);
+        let mut bytes = FINGERPRINT_VERSION.to_le_bytes().to_vec();
+
+        while let Some(state) = queue.pop_front() {
+            let state =
+                self.transition_matrix.state(state.number).unwrap_or(&state);
+            bytes.push(state.is_final as u8);
+            bytes.push(state.is_error as u8);
+
+            let mut transitions: Vec<(&str, &State)> = self
+                .transition_matrix
+                .transitions_from(state.number)
+                .collect();
+            transitions.sort_by_key(|&(symbol, _)| symbol);
+            bytes.extend((transitions.len() as u64).to_le_bytes());
+
+            for (symbol, to_state) in transitions {
+                let count = numbers.len();
+                let number =
+                    *numbers.entry(to_state.number).or_insert_with(|| {
+                        queue.push_back(*to_state);
+                        count
+                    });
+
+                bytes.extend((symbol.len() as u64).to_le_bytes());
+                bytes.extend(symbol.as_bytes());
+                bytes.extend((number as u64).to_le_bytes());
+            }
+        }
+
+        // 64-bit FNV-1a.
+        bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    /// Returns true iff the automaton accepts finitely many strings, i.e. no
+    /// cycle passes through a state that is both reachable from the start and
+    /// able to reach acceptance.
+    pub fn is_finite(&self) -> bool {
+        let useful: HashSet<StateIndex> = self
+            .reachable_states()
+            .intersection(&self.live_states())
+            .copied()
+            .collect();
+
+        // Kahn's algorithm: the useful subgraph is acyclic iff every state
+        // can be removed once its in-degree drops to zero.
+        let mut in_degree: HashMap<StateIndex, usize> =
+            useful.iter().map(|&state| (state, 0)).collect();
+        for &state in useful.iter() {
+            for target in self.successors(state) {
+                if let Some(degree) = in_degree.get_mut(&target) {
+                    *degree += 1;
+                }
+            }
+        }
+
+        let mut queue: VecDeque<StateIndex> = in_degree
+            .iter()
+            .filter(|(_, &degree)| degree == 0)
+            .map(|(&state, _)| state)
+            .collect();
+        let mut removed = 0;
+        while let Some(state) = queue.pop_front() {
+            removed += 1;
+            for target in self.successors(state) {
+                if let Some(degree) = in_degree.get_mut(&target) {
+                    *degree -= 1;
+                    if *degree == 0 {
+                        queue.push_back(target);
+                    }
+                }
+            }
+        }
+
+        removed == useful.len()
+    }
+
+    /// Returns true iff the number of accepted strings of length `n` grows
+    /// exponentially with `n`, i.e. some useful state lies on two distinct
+    /// cycles. Otherwise every strongly connected part of the useful states
+    /// is a single cycle and the growth is polynomial.
+    pub fn is_exponential_growth(&self) -> bool {
+        let edges = self.useful_edges();
+        let mut successors: HashMap<StateIndex, Vec<StateIndex>> =
+            HashMap::new();
+        for &(from_state, _, to_state) in edges.iter() {
+            successors.entry(from_state).or_default().push(to_state);
+        }
+        let reaches = |from_state: StateIndex, to_state: StateIndex| {
+            let mut visited = HashSet::from([from_state]);
+            let mut stack = vec![from_state];
+            while let Some(state) = stack.pop() {
+                if state == to_state {
+                    return true;
+                }
+                for &target in successors.get(&state).into_iter().flatten() {
+                    if visited.insert(target) {
+                        stack.push(target);
+                    }
+                }
+            }
+            false
+        };
+
+        // A strongly connected part is a single cycle iff each of its states
+        // has exactly one transition staying inside it.
+        let mut cycle_edges: HashMap<StateIndex, usize> = HashMap::new();
+        for &(from_state, _, to_state) in edges.iter() {
+            if reaches(to_state, from_state) {
+                *cycle_edges.entry(from_state).or_default() += 1;
+            }
+        }
+
+        cycle_edges.values().any(|&count| count > 1)
+    }
+
+    /// Returns a readable regex for the accepted language, built by state
+    /// elimination with a few algebraic simplifications; `∅` if nothing is
+    /// accepted. The result is not necessarily the shortest such regex.
+    pub fn describe(&self) -> String {
+        let count = self.state_count();
+        let (start, end) = (count, count + 1);
+        let mut edges: HashMap<(StateIndex, StateIndex), Expression> =
+            HashMap::new();
+        let add_edge = |edges: &mut HashMap<_, _>, key, expression| {
+            let expression = match edges.remove(&key) {
+                None => expression,
+                Some(existing) => Expression::union(existing, expression),
+            };
+            edges.insert(key, expression);
+        };
+
+        let useful_edges = self.useful_edges();
+        let mut useful: BTreeSet<StateIndex> = BTreeSet::new();
+        for &(from_state, symbol, to_state) in useful_edges.iter() {
+            useful.extend([from_state, to_state]);
+            let symbol = Expression::Symbol(symbol.to_string());
+            add_edge(&mut edges, (from_state, to_state), symbol);
+        }
+        let initial = self.transition_matrix.start_state().number;
+        if self.accepts_index(Some(initial)) {
+            useful.insert(initial);
+        }
+        if useful.contains(&initial) {
+            add_edge(&mut edges, (start, initial), Expression::Epsilon);
+        }
+        for &state in useful.iter() {
+            if self.accepts_index(Some(state)) {
+                add_edge(&mut edges, (state, end), Expression::Epsilon);
+            }
+        }
+
+        for &state in useful.iter() {
+            let repeat = edges.remove(&(state, state)).map(Expression::star);
+            let keys: Vec<(StateIndex, StateIndex)> = edges
+                .keys()
+                .filter(|&&(from_state, to_state)| {
+                    from_state == state || to_state == state
+                })
+                .copied()
+                .collect();
+            let mut incoming = Vec::new();
+            let mut outgoing = Vec::new();
+            for key in keys {
+                let expression = edges.remove(&key).unwrap();
+                match key {
+                    (from_state, to_state) if to_state == state => {
+                        incoming.push((from_state, expression))
+                    }
+                    (_, to_state) => outgoing.push((to_state, expression)),
+                }
+            }
+
+            for (from_state, before) in incoming.iter() {
+                for (to_state, after) in outgoing.iter() {
+                    let mut path = before.clone();
+                    if let Some(repeat) = &repeat {
+                        path = Expression::concat(path, repeat.clone());
+                    }
+                    path = Expression::concat(path, after.clone());
+                    add_edge(&mut edges, (*from_state, *to_state), path);
+                }
+            }
+        }
+
+        match edges.get(&(start, end)) {
+            None => "∅".to_string(),
+            Some(expression) => expression.to_str(),
+        }
+    }
+
+    /// Returns a shortest string accepted from exactly one of the states `a`
+    /// and `b`, or `None` if the two states are equivalent over `alphabet`.
+    pub fn distinguishing_string(
+        &self,
+        a: StateIndex,
+        b: StateIndex,
+        alphabet: &[char],
+    ) -> Option<String> {
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+
+        visited.insert((Some(a), Some(b)));
+        queue.push_back((Some(a), Some(b), String::new()));
+
+        while let Some((a, b, witness)) = queue.pop_front() {
+            if self.accepts_index(a) != self.accepts_index(b) {
+                return Some(witness);
+            }
+
+            for &symbol in alphabet {
+                let next = (
+                    self.transition_index(a, symbol),
+                    self.transition_index(b, symbol),
+                );
+                if next == (None, None) || !visited.insert(next) {
+                    continue;
+                }
+
+                let mut next_witness = witness.clone();
+                next_witness.push(symbol);
+                queue.push_back((next.0, next.1, next_witness));
+            }
+        }
+
+        None
+    }
+
+    fn transition(&self, state: &State, symbol: char) -> Option<&State> {
+        self.transition_matrix
+            .transition(state, symbol.encode_utf8(&mut [0; 4]))
+    }
+
+    // Transitions between states that are reachable and can still accept.
+    fn useful_edges(&self) -> Vec<(StateIndex, &str, StateIndex)> {
+        let live = self.live_states();
+        let reachable = self.reachable_states();
+        let useful = |state: &StateIndex| {
+            live.contains(state) && reachable.contains(state)
+        };
+
+        self.transition_matrix
+            .transitions()
+            .map(|(number, symbol, state)| (number, symbol, state.number))
+            .filter(|(from_state, _, to_state)| {
+                useful(from_state) && useful(to_state)
+            })
+            .collect()
+    }
+
+    // The number of transitions on a shortest path from the start.
+    fn distance(&self, state: StateIndex) -> usize {
+        let start = self.transition_matrix.start_state().number;
+        let mut distances = HashMap::from([(start, 0)]);
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(current) = queue.pop_front() {
+            if current == state {
+                break;
+            }
+            for target in self.successors(current) {
+                if !distances.contains_key(&target) {
+                    distances.insert(target, distances[&current] + 1);
+                    queue.push_back(target);
+                }
+            }
+        }
+
+        distances.get(&state).copied().unwrap_or(usize::MAX)
+    }
+
+    fn state_count(&self) -> usize {
+        self.transition_matrix
+            .transitions()
+            .flat_map(|(number, _, state)| [number, state.number])
+            .chain([self.transition_matrix.start_state().number])
+            .max()
+            .unwrap()
+            + 1
+    }
+
+    fn successors(
+        &self,
+        state: StateIndex,
+    ) -> impl Iterator<Item = StateIndex> + '_ {
+        self.transition_matrix
+            .transitions_from(state)
+            .map(|(_, target)| target.number)
+    }
+
+    fn reachable_states(&self) -> HashSet<StateIndex> {
+        let start = self.transition_matrix.start_state().number;
+        let mut reachable = HashSet::from([start]);
+        let mut stack = vec![start];
+
+        while let Some(state) = stack.pop() {
+            for target in self.successors(state) {
+                if reachable.insert(target) {
+                    stack.push(target);
+                }
+            }
+        }
+
+        reachable
+    }
+
+    // States from which an accepting state can be reached.
+    fn live_states(&self) -> HashSet<StateIndex> {
+        let mut predecessors: HashMap<StateIndex, Vec<StateIndex>> =
+            HashMap::new();
+        let mut live = HashSet::new();
+
+        for state in self.reachable_states() {
+            if self.accepts_index(Some(state)) {
+                live.insert(state);
+            }
+            for target in self.successors(state) {
+                predecessors.entry(target).or_default().push(state);
+            }
+        }
+
+        let mut stack: Vec<StateIndex> = live.iter().copied().collect();
+        while let Some(state) = stack.pop() {
+            for &source in predecessors.get(&state).into_iter().flatten() {
+                if live.insert(source) {
+                    stack.push(source);
+                }
+            }
+        }
+
+        live
+    }
+
+    // A state standing for a set of states of `self`: it accepts if any of
+    // them accepts and is an error state only if all of them are.
+    fn subset_state(
+        &self,
+        number: StateIndex,
+        subset: &BTreeSet<StateIndex>,
+    ) -> State {
+        let states: Vec<&State> = subset
+            .iter()
+            .filter_map(|&state| self.transition_matrix.state(state))
+            .collect();
+
+        State::new(
+            number,
+            states.iter().any(|state| state.is_accepting()),
+            !states.is_empty() && states.iter().all(|state| state.is_error),
+        )
+    }
+
+    fn longest_match(&self, haystack: &str, start: usize) -> Option<usize> {
+        let mut current_state = self.transition_matrix.start_state();
+        let mut end = current_state.is_accepting().then_some(start);
+
+        for (offset, symbol) in haystack[start..].char_indices() {
+            match self.transition(current_state, symbol) {
+                None => break,
+                Some(state) => current_state = state,
+            }
+            if current_state.is_accepting() {
+                end = Some(start + offset + symbol.len_utf8());
+            }
+        }
+
+        end
+    }
+
+    fn transition_index(
+        &self,
+        index: Option<StateIndex>,
+        symbol: char,
+    ) -> Option<StateIndex> {
+        self.transition_matrix
+            .target(index?, symbol.encode_utf8(&mut [0; 4]))
+            .map(|state| state.number)
+    }
+
+    fn accepts_index(&self, index: Option<StateIndex>) -> bool {
+        index
+            .and_then(|index| self.transition_matrix.state(index))
+            .is_some_and(|state| state.is_accepting())
+    }
+}
+
+impl Default for Automaton {
+    fn default() -> Automaton {
+        Automaton::new()
+    }
+}
+
+// A regex over transition symbols, simplified as it is built.
+#[derive(Clone, PartialEq)]
+enum Expression {
+    Epsilon,
+    Symbol(Symbol),
+    Concat(Vec<Expression>),
+    Union(Vec<Expression>),
+    Star(Box<Expression>),
+    Plus(Box<Expression>),
+    Optional(Box<Expression>),
+}
+
+impl Expression {
+    fn concat(left: Expression, right: Expression) -> Expression {
+        let mut items = Vec::new();
+        for expression in [left, right] {
+            match expression {
+                Expression::Epsilon => {}
+                Expression::Concat(inner) => items.extend(inner),
+                expression => items.push(expression),
+            }
+        }
+
+        // `x x*` is `x+`.
+        let mut folded: Vec<Expression> = Vec::new();
+        for item in items {
+            match (folded.last(), &item) {
+                (Some(last), Expression::Star(inner)) if **inner == *last => {
+                    let last = folded.pop().unwrap();
+                    folded.push(Expression::Plus(Box::new(last)));
+                }
+                _ => folded.push(item),
+            }
+        }
+
+        match folded.len() {
+            0 => Expression::Epsilon,
+            1 => folded.pop().unwrap(),
+            _ => Expression::Concat(folded),
+        }
+    }
+
+    fn union(left: Expression, right: Expression) -> Expression {
+        let mut items: Vec<Expression> = Vec::new();
+        let mut has_epsilon = false;
+        for expression in [left, right] {
+            let alternatives = match expression {
+                Expression::Union(inner) => inner,
+                Expression::Optional(inner) => {
+                    has_epsilon = true;
+                    vec![*inner]
+                }
+                expression => vec![expression],
+            };
+            for alternative in alternatives {
+                match alternative {
+                    Expression::Epsilon => has_epsilon = true,
+                    // `a|a` is `a`.
+                    alternative if items.contains(&alternative) => {}
+                    alternative => items.push(alternative),
+                }
+            }
+        }
+        items.sort_by_key(|item| item.to_str());
+
+        let expression = match items.len() {
+            0 => return Expression::Epsilon,
+            1 => items.pop().unwrap(),
+            _ => Expression::factor(&items).unwrap_or(Expression::Union(items)),
+        };
+        if !has_epsilon {
+            return expression;
+        }
+
+        // `ε|x` is `x?`, and `x*` already accepts the empty string.
+        match expression {
+            Expression::Star(_) | Expression::Optional(_) => expression,
+            Expression::Plus(inner) => Expression::Star(inner),
+            expression => Expression::Optional(Box::new(expression)),
+        }
+    }
+
+    // Pulls a first or last item shared by all alternatives out of the
+    // union, e.g. `ac|bc` becomes `(a|b)c`.
+    fn factor(alternatives: &[Expression]) -> Option<Expression> {
+        let sequences: Vec<&[Expression]> = alternatives
+            .iter()
+            .map(|alternative| match alternative {
+                Expression::Concat(items) => items.as_slice(),
+                alternative => std::slice::from_ref(alternative),
+            })
+            .collect();
+        let rest = |sequences: Vec<&[Expression]>| {
+            sequences
+                .into_iter()
+                .map(|sequence| {
+                    sequence
+                        .iter()
+                        .cloned()
+                        .fold(Expression::Epsilon, Expression::concat)
+                })
+                .reduce(Expression::union)
+                .unwrap()
+        };
+
+        let first = sequences[0].first();
+        if sequences.iter().all(|sequence| sequence.first() == first) {
+            let tails = sequences.iter().map(|sequence| &sequence[1..]);
+            return Some(Expression::concat(
+                first?.clone(),
+                rest(tails.collect()),
+            ));
+        }
+        let last = sequences[0].last();
+        if sequences.iter().all(|sequence| sequence.last() == last) {
+            let heads = sequences
+                .iter()
+                .map(|sequence| &sequence[..sequence.len() - 1]);
+            return Some(Expression::concat(
+                rest(heads.collect()),
+                last?.clone(),
+            ));
+        }
+
+        None
+    }
+
+    fn star(expression: Expression) -> Expression {
+        match expression {
+            Expression::Epsilon => Expression::Epsilon,
+            Expression::Star(inner)
+            | Expression::Plus(inner)
+            | Expression::Optional(inner) => Expression::Star(inner),
+            expression => Expression::Star(Box::new(expression)),
+        }
+    }
+
+    fn to_str(&self) -> String {
+        match self {
+            Expression::Epsilon => "ε".to_string(),
+            Expression::Symbol(symbol) => symbol.clone(),
+            Expression::Concat(items) => items
+                .iter()
+                .map(|item| match item {
+                    Expression::Union(_) => format!("({})", item.to_str()),
+                    item => item.to_str(),
+                })
+                .collect(),
+            Expression::Union(items) => items
+                .iter()
+                .map(|item| item.to_str())
+                .collect::<Vec<String>>()
+                .join("|"),
+            Expression::Star(inner) => inner.to_postfix_operand() + "*",
+            Expression::Plus(inner) => inner.to_postfix_operand() + "+",
+            Expression::Optional(inner) => inner.to_postfix_operand() + "?",
+        }
+    }
+
+    fn to_postfix_operand(&self) -> String {
+        match self {
+            Expression::Symbol(symbol) if symbol.chars().count() == 1 => {
+                symbol.clone()
+            }
+            expression => format!("({})", expression.to_str()),
+        }
+    }
+}
+
+/// A DFA with a transition for every state and alphabet symbol, where all
+/// missing transitions lead to a non-accepting sink state.
+pub struct CompleteDfa {
+    table: Vec<Vec<StateIndex>>,
+    columns: HashMap<char, usize>,
+    accepting: Vec<bool>,
+    start: StateIndex,
+    sink: StateIndex,
+}
+
+impl CompleteDfa {
+    pub fn start(&self) -> StateIndex {
+        self.start
+    }
+
+    pub fn sink(&self) -> StateIndex {
+        self.sink
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Symbols outside the alphabet lead to the sink.
+    pub fn transition(&self, state: StateIndex, symbol: char) -> StateIndex {
+        match self.columns.get(&symbol) {
+            None => self.sink,
+            Some(&column) => self.table[state][column],
+        }
+    }
+
+    pub fn consume(&self, sequence: impl AsRef<str>) -> bool {
+        let end = sequence
+            .as_ref()
+            .chars()
+            .fold(self.start, |state, symbol| self.transition(state, symbol));
+
+        self.accepting[end]
+    }
+
+    /// Returns the product automaton accepting the union of both languages.
+    /// Both must have been completed against the same `SymbolInterner`, so
+    /// that columns are paired by id without looking symbols up; a column
+    /// interned after one side was built leads to that side's sink.
+    pub fn union(&self, other: &CompleteDfa) -> CompleteDfa {
+        let (wider, narrower) = if self.columns.len() >= other.columns.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        debug_assert!(narrower.columns.iter().all(|(symbol, column)| {
+            wider.columns.get(symbol) == Some(column)
+        }));
+        let width = wider.columns.len();
+        let target = |dfa: &CompleteDfa, state: StateIndex, column: usize| {
+            dfa.table[state].get(column).copied().unwrap_or(dfa.sink)
+        };
+
+        let mut pairs = vec![(self.start, other.start)];
+        let mut numbers = HashMap::from([(pairs[0], 0)]);
+        let mut table = Vec::new();
+
+        let mut number = 0;
+        while number < pairs.len() {
+            let (state, other_state) = pairs[number];
+            let row = (0..width)
+                .map(|column| {
+                    let pair = (
+                        target(self, state, column),
+                        target(other, other_state, column),
+                    );
+                    *numbers.entry(pair).or_insert_with(|| {
+                        pairs.push(pair);
+                        pairs.len() - 1
+                    })
+                })
+                .collect();
+            table.push(row);
+
+            number += 1;
+        }
+
+        let sink = match numbers.get(&(self.sink, other.sink)) {
+            Some(&sink) => sink,
+            None => {
+                pairs.push((self.sink, other.sink));
+                table.push(vec![pairs.len() - 1; width]);
+                pairs.len() - 1
+            }
+        };
+
+        CompleteDfa {
+            table,
+            columns: wider.columns.clone(),
+            accepting: pairs
+                .iter()
+                .map(|&(state, other_state)| {
+                    self.accepting[state] || other.accepting[other_state]
+                })
+                .collect(),
+            start: 0,
+            sink,
+        }
+    }
+}
+
+/// Assigns each symbol a dense id, shared by every automaton completed
+/// against the same interner.
+#[derive(Default)]
+pub struct SymbolInterner {
+    ids: HashMap<char, usize>,
+    symbols: Vec<char>,
+}
+
+impl SymbolInterner {
+    pub fn new() -> SymbolInterner {
+        SymbolInterner::default()
+    }
+
+    pub fn intern(&mut self, symbol: char) -> usize {
+        *self.ids.entry(symbol).or_insert_with(|| {
+            self.symbols.push(symbol);
+            self.symbols.len() - 1
+        })
+    }
+
+    pub fn get(&self, symbol: char) -> Option<usize> {
+        self.ids.get(&symbol).copied()
+    }
+
+    pub fn symbols(&self) -> &[char] {
+        &self.symbols
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// A DFA whose transitions each emit an output string (a Mealy machine).
+#[derive(Default)]
+pub struct Transducer {
+    automaton: Automaton,
+    outputs: HashMap<(StateIndex, Symbol), String>,
+}
+
+impl Transducer {
+    pub fn new() -> Transducer {
+        Transducer::default()
+    }
+
+    pub fn add_transition(
+        &mut self,
+        from_state: State,
+        to_state: State,
+        symbol: &str,
+        output: &str,
+    ) {
+        self.automaton.add_transition(from_state, to_state, symbol);
+        self.outputs.insert(
+            (from_state.number, symbol.to_string()),
+            output.to_string(),
+        );
+    }
+
+    /// Returns the concatenated outputs of the transitions taken, if the
+    /// underlying automaton accepts `input`.
+    pub fn transduce(&self, input: &str) -> Option<String> {
+        let mut output = String::new();
+
+        self.automaton
+            .consume_with_observer(input, &mut |from_state, symbol, _| {
+                let key = (from_state.number, symbol.to_string());
+                if let Some(emitted) = self.outputs.get(&key) {
+                    output.push_str(emitted);
+                }
+            })
+            .then_some(output)
+    }
+}
+
+/// Records which transitions of an automaton a corpus of inputs exercises.
+pub struct CoverageTracker<'a> {
+    automaton: &'a Automaton,
+    covered: HashSet<(StateIndex, Symbol)>,
+}
+
+impl<'a> CoverageTracker<'a> {
+    pub fn new(automaton: &'a Automaton) -> CoverageTracker<'a> {
+        CoverageTracker {
+            automaton,
+            covered: HashSet::new(),
+        }
+    }
+
+    pub fn record(&mut self, input: &str) -> bool {
+        let covered = &mut self.covered;
+
+        self.automaton.consume_with_observer(
+            input,
+            &mut |from_state, symbol, _| {
+                covered.insert((from_state.number, symbol.to_string()));
+            },
+        )
+    }
+
+    pub fn record_all(&mut self, inputs: &[&str]) {
+        for input in inputs {
+            self.record(input);
+        }
+    }
+
+    /// Returns the `(from, symbol)` pairs of the transitions never taken.
+    pub fn uncovered(&self) -> Vec<(StateIndex, Symbol)> {
+        let mut uncovered: Vec<(StateIndex, Symbol)> = self
+            .automaton
+            .transition_matrix
+            .transitions()
+            .map(|(number, symbol, _)| (number, symbol.to_string()))
+            .filter(|transition| !self.covered.contains(transition))
+            .collect();
+        uncovered.sort();
+
+        uncovered
+    }
+}
+
+/// Keeps the state reached after every prefix of a buffer, so that edits
+/// only re-run the automaton until it falls back into a known state.
+/// Positions are counted in chars.
+pub struct IncrementalMatcher<'a> {
+    automaton: &'a Automaton,
+    text: Vec<char>,
+    states: Vec<Option<StateIndex>>,
+}
+
+impl<'a> IncrementalMatcher<'a> {
+    pub fn new(automaton: &'a Automaton, text: &str) -> IncrementalMatcher<'a> {
+        let mut matcher = IncrementalMatcher {
+            automaton,
+            text: Vec::new(),
+            states: vec![Some(
+                automaton.transition_matrix.start_state().number,
+            )],
+        };
+        matcher.relex_range(0, 0, text);
+
+        matcher
+    }
+
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    /// Returns the state after consuming the first `position` chars, or
+    /// `None` if the automaton got stuck before reaching it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `position` is past the end of the text.
+    pub fn state_at(&self, position: usize) -> Option<StateIndex> {
+        self.states[position]
+    }
+
+    pub fn is_match(&self) -> bool {
+        self.automaton.accepts_index(self.states[self.text.len()])
+    }
+
+    /// Replaces the chars in `start..end` with `new_text` and returns how
+    /// many state snapshots had to be recomputed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start > end` or `end` is past the end of the text.
+    pub fn relex_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        new_text: &str,
+    ) -> usize {
+        let new_text: Vec<char> = new_text.chars().collect();
+        let inserted = new_text.len();
+        self.text.splice(start..end, new_text);
+
+        let old_suffix = self.states[end..].to_vec();
+        self.states.truncate(start + 1);
+
+        // A pure deletion lines `start` up with the old snapshot at `end`.
+        if inserted == 0 && old_suffix[0] == self.states[start] {
+            self.states.extend_from_slice(&old_suffix[1..]);
+            return 0;
+        }
+
+        let mut recomputed = 0;
+        let mut state = self.states[start];
+        for position in start..self.text.len() {
+            state = self.automaton.transition_index(state, self.text[position]);
+            recomputed += 1;
+
+            let edited = position + 1 - start;
+            if edited >= inserted {
+                // Past the edit, positions line up with the old snapshots,
+                // and matching states mean the rest is unchanged.
+                let old_position = edited - inserted;
+                if old_suffix.get(old_position) == Some(&state) {
+                    self.states.extend_from_slice(&old_suffix[old_position..]);
+                    return recomputed;
+                }
+            }
+            self.states.push(state);
+        }
+
+        recomputed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_automaton() -> Automaton {
+        let start = State::new(0, false, false);
+        let first = State::new(1, false, false);
+        let second = State::new(2, true, false);
+
+        let mut automaton = Automaton::new();
+        automaton.add_transition(start, first, "a");
+        automaton.add_transition(first, second, "b");
+
+        automaton
+    }
+
+    // Every string over `alphabet` of at most `length` symbols, shortest
+    // first.
+    pub(super) fn strings_over(
+        alphabet: &[char],
+        length: usize,
+    ) -> Vec<String> {
+        let mut strings = vec![String::new()];
+        let mut last = vec![String::new()];
+
+        for _ in 0..length {
+            last = last
+                .iter()
+                .flat_map(|prefix| {
+                    alphabet.iter().map(move |&symbol| {
+                        let mut string = prefix.clone();
+                        string.push(symbol);
+                        string
+                    })
+                })
+                .collect();
+            strings.extend(last.iter().cloned());
+        }
+
+        strings
+    }
+
+    #[test]
+    fn test_consume_ab() {
+        let automaton = create_automaton();
+        assert!(automaton.consume("ab"));
+    }
+
+    #[test]
+    fn test_consume_abc() {
+        let automaton = create_automaton();
+        assert!(!automaton.consume("abc"));
+    }
+
+    #[test]
+    fn test_consume_cab() {
+        let automaton = create_automaton();
+        assert!(!automaton.consume("cab"));
+    }
+
+    #[test]
+    fn test_quotient_symbols() {
+        let start = State::new(0, false, false);
+        let first = State::new(1, true, false);
+        let second = State::new(2, false, false);
+
+        let mut automaton = Automaton::new();
+        automaton.add_transition(start, first, "a");
+        automaton.add_transition(start, second, "b");
+        automaton.add_transition(second, first, "c");
+        automaton.add_transition(first, first, "x");
+        assert!(!automaton.consume("c"));
+
+        let quotient = automaton.quotient_symbols(&[vec!['a', 'b', 'c']]);
+        for input in ["a", "b", "c", "ba", "ca", "cc", "ax", "cxx"] {
+            assert!(quotient.consume(input), "{}", input);
+        }
+        for input in ["", "x", "xa", "aaa", "axa"] {
+            assert!(!quotient.consume(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_quotient_symbols_accepting_start() {
+        let automaton = Automaton::from_transition_list("final 0").unwrap();
+        let quotient = automaton.quotient_symbols(&[vec!['a']]);
+
+        assert!(quotient.consume(""));
+        assert!(!quotient.consume("a"));
+    }
+
+    fn create_a_plus() -> Automaton {
+        let start = State::new(0, false, false);
+        let end = State::new(1, true, false);
+
+        let mut automaton = Automaton::new();
+        automaton.add_transition(start, end, "a");
+        automaton.add_transition(end, end, "a");
+
+        automaton
+    }
+
+    #[test]
+    fn test_with_length_bounds() {
+        let automaton = create_a_plus().with_length_bounds(2, Some(3));
+
+        assert!(!automaton.consume("a"));
+        assert!(automaton.consume("aa"));
+        assert!(automaton.consume("aaa"));
+        assert!(!automaton.consume("aaaa"));
+    }
+
+    #[test]
+    fn test_with_length_bounds_unbounded() {
+        let automaton = create_a_plus().with_length_bounds(3, None);
+
+        assert!(!automaton.consume("aa"));
+        assert!(automaton.consume("aaa"));
+        assert!(automaton.consume("aaaaaaa"));
+        assert!(!automaton.consume("aaab"));
+    }
+
+    #[test]
+    fn test_with_length_bounds_empty_string() {
+        let start = State::new(0, true, false);
+        let mut automaton = Automaton::new();
+        automaton.set_start_state(start);
+        automaton.add_transition(start, start, "a");
+
+        assert!(automaton.with_length_bounds(0, Some(1)).consume(""));
+        assert!(!automaton.with_length_bounds(1, Some(1)).consume(""));
+    }
+
+    #[test]
+    fn test_star() {
+        let automaton = create_automaton().star();
+
+        for input in ["", "ab", "abab", "ababab"] {
+            assert!(automaton.consume(input), "{}", input);
+        }
+        for input in ["a", "aba", "ba", "abb", "aab"] {
+            assert!(!automaton.consume(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_star_with_looping_start() {
+        let automaton =
+            Automaton::from_transition_list("0 a 0\n0 b 1\nfinal 1")
+                .unwrap()
+                .star();
+
+        for input in ["", "b", "ab", "aabb", "bab"] {
+            assert!(automaton.consume(input), "{}", input);
+        }
+        for input in ["a", "ba", "aaba"] {
+            assert!(!automaton.consume(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let automaton = create_automaton();
+
+        assert_eq!(automaton.suggestions(""), vec!['a']);
+        assert_eq!(automaton.suggestions(String::from("a")), vec!['b']);
+        assert!(automaton.suggestions("ab").is_empty());
+        assert!(automaton.suggestions("b").is_empty());
+    }
+
+    #[test]
+    fn test_suggestions_skip_dead_ends() {
+        let automaton = Automaton::from_transition_list(
+            "0 a 1\n0 b 2\n0 c 3\n3 c 3\nfinal 1 2",
+        )
+        .unwrap();
+
+        assert_eq!(automaton.suggestions(""), vec!['a', 'b']);
+    }
+
+    #[test]
+    fn test_transition_table() {
+        let automaton = create_automaton();
+
+        assert_eq!(
+            automaton.transition_table(&['a', 'b', 'c']),
+            vec![
+                vec![Some(1), None, None],
+                vec![None, Some(2), None],
+                vec![None, None, None],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_transition_table_source_only_state() {
+        let automaton =
+            Automaton::from_transition_list("start 0\n1 a 2\nfinal 2").unwrap();
+
+        assert_eq!(
+            automaton.transition_table(&['a']),
+            vec![vec![None], vec![Some(2)], vec![None]]
+        );
+    }
+
+    #[test]
+    fn test_complete_union_with_shared_interner() {
+        let mut interner = SymbolInterner::new();
+        let ab = create_automaton().into_complete_interned(&mut interner);
+        let a_id = interner.get('a');
+
+        let ba = Automaton::from_transition_list("final 2\n0 b 1\n1 a 2")
+            .unwrap()
+            .into_complete_interned(&mut interner);
+        assert_eq!(interner.symbols(), ['a', 'b']);
+        assert_eq!(interner.get('a'), a_id);
+
+        let c_plus = Automaton::from_transition_list("final 1\n0 c 1\n1 c 1")
+            .unwrap()
+            .into_complete_interned(&mut interner);
+        assert_eq!(interner.len(), 3);
+
+        let union = ab.union(&ba).union(&c_plus);
+        for input in ["ab", "ba", "c", "ccc"] {
+            assert!(union.consume(input), "{:?}", input);
+        }
+        for input in ["", "a", "aa", "abc", "cab", "d"] {
+            assert!(!union.consume(input), "{:?}", input);
+        }
+        for symbol in interner.symbols() {
+            assert_eq!(union.transition(union.sink(), *symbol), union.sink());
+        }
+    }
+
+    #[test]
+    fn test_into_complete_with_sink() {
+        let alphabet = ['a', 'b'];
+        let complete = create_automaton().into_complete_with_sink(&alphabet);
+
+        assert_eq!(complete.state_count(), 4);
+        for state in 0..complete.state_count() {
+            for symbol in alphabet {
+                assert!(
+                    complete.transition(state, symbol) < complete.state_count()
+                );
+            }
+        }
+        for symbol in alphabet {
+            assert_eq!(
+                complete.transition(complete.sink(), symbol),
+                complete.sink()
+            );
+        }
+
+        let automaton = create_automaton();
+        for input in ["", "a", "ab", "abc", "ba", "abab", "cab", "b"] {
+            assert_eq!(
+                complete.consume(input),
+                automaton.consume(input),
+                "{}",
+                input
+            );
+        }
+        assert!(complete.consume(String::from("ab")));
+    }
+
+    #[test]
+    fn test_required_literals() {
+        let digits: Vec<char> = ('0'..='9').collect();
+        let mut automaton = Automaton::from_transition_list(
+            "0 f 1\n1 o 2\n2 o 3\n4 b 5\n5 a 6\n6 r 7\nfinal 7",
+        )
+        .unwrap();
+        let third = State::new(3, false, false);
+        let fourth = State::new(4, false, false);
+        automaton.add_set_transition(third, fourth, &digits);
+        automaton.add_set_transition(fourth, fourth, &digits);
+
+        assert!(automaton.consume("foo42bar"));
+        assert_eq!(automaton.required_literals(), vec!["foo", "bar"]);
+    }
+
+    #[test]
+    fn test_required_literals_alternation() {
+        let automaton = Automaton::from_transition_list(
+            "0 a 1\n0 b 1\n1 c 2\n2 x 2\n2 d 3\nfinal 3",
+        )
+        .unwrap();
+
+        assert_eq!(automaton.required_literals(), vec!["c", "d"]);
+        assert_eq!(create_a_plus().required_literals(), vec!["a"]);
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        let mut automaton =
+            Automaton::from_transition_list("0 a 1\n1 b 2\n2 c 3\nfinal 3")
+                .unwrap();
+        let end = State::new(3, true, false);
+        let digits: Vec<char> = ('0'..='9').collect();
+        automaton.add_set_transition(end, end, &digits);
+
+        assert_eq!(automaton.literal_prefix(), Some("abc".to_string()));
+        assert_eq!(automaton.find("xxabc12y"), Some(Span::new(2, 7)));
+        assert_eq!(automaton.find("ab abc"), Some(Span::new(3, 6)));
+        assert_eq!(automaton.find("abab"), None);
+    }
+
+    #[test]
+    fn test_literal_prefix_alternation() {
+        let automaton =
+            Automaton::from_transition_list("0 a 1\n0 b 1\n1 c 2\nfinal 2")
+                .unwrap();
+
+        assert_eq!(automaton.literal_prefix(), None);
+        assert_eq!(automaton.find("xbc"), Some(Span::new(1, 3)));
+    }
+
+    #[test]
+    fn test_automaton_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Automaton>();
+    }
+
+    #[test]
+    fn test_find_after_add_transition() {
+        let mut automaton =
+            Automaton::from_transition_list("0 a 1\n1 c 2\nfinal 2").unwrap();
+        assert_eq!(automaton.find("xbc"), None);
+
+        let start = State::new(0, false, false);
+        automaton.add_transition(start, State::new(1, false, false), "b");
+        assert_eq!(automaton.find("xbc"), Some(Span::new(1, 3)));
+    }
+
+    #[test]
+    fn test_is_finite() {
+        let automaton = create_automaton();
+        assert!(automaton.is_finite());
+
+        let start = State::new(0, true, false);
+        let mut automaton = Automaton::new();
+        automaton.set_start_state(start);
+        automaton.add_transition(start, start, "a");
+        assert!(automaton.consume(""));
+        assert!(automaton.consume("aaa"));
+        assert!(!automaton.is_finite());
+    }
+
+    #[test]
+    fn test_is_finite_ignores_dead_cycles() {
+        let start = State::new(0, false, false);
+        let end = State::new(1, true, false);
+        let dead = State::new(2, false, false);
+
+        let mut automaton = Automaton::new();
+        automaton.add_transition(start, end, "a");
+        automaton.add_transition(start, dead, "b");
+        automaton.add_transition(dead, dead, "b");
+        assert!(automaton.is_finite());
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let length = 10_000;
+        let mut automaton = Automaton::with_capacity(length + 1);
+        for number in 0..length {
+            let from_state = State::new(number, false, false);
+            let to_state = State::new(number + 1, number + 1 == length, false);
+            automaton.add_transition(from_state, to_state, "a");
+        }
+
+        assert!(automaton.consume("a".repeat(length)));
+        assert!(!automaton.consume("a".repeat(length - 1)));
+    }
+
+    #[test]
+    fn test_find() {
+        let automaton = create_automaton();
+        let span = automaton.find("xxaby").unwrap();
+        assert_eq!(span, Span::new(2, 4));
+        assert_eq!(span.as_str("xxaby"), "ab");
+        assert_eq!(automaton.find("ba"), None);
+    }
+
+    #[test]
+    fn test_find_iter() {
+        let automaton = create_automaton();
+        let spans: Vec<Span> = automaton.find_iter("abxaabab").collect();
+
+        assert_eq!(
+            spans,
+            vec![Span::new(0, 2), Span::new(4, 6), Span::new(6, 8)]
+        );
+        assert_eq!(automaton.find_iter("ba").count(), 0);
+    }
+
+    #[test]
+    fn test_find_iter_empty_matches() {
+        let state = State::new(0, true, false);
+        let mut automaton = Automaton::new();
+        automaton.set_start_state(state);
+        automaton.add_transition(state, state, "a");
+
+        let spans: Vec<Span> = automaton.find_iter("bb").collect();
+        assert_eq!(
+            spans,
+            vec![Span::new(0, 0), Span::new(1, 1), Span::new(2, 2)]
+        );
+
+        let spans: Vec<Span> = automaton.find_iter("baaéb").collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(0, 0),
+                Span::new(1, 3),
+                Span::new(3, 3),
+                Span::new(5, 5),
+                Span::new(6, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_consume_with_observer() {
+        let automaton = create_automaton();
+        let mut transitions = Vec::new();
+
+        let accepted = automaton.consume_with_observer(
+            "ab",
+            &mut |from_state, symbol, to_state| {
+                transitions.push((from_state.number, symbol, to_state.number))
+            },
+        );
+
+        assert!(accepted);
+        assert_eq!(transitions, vec![(0, 'a', 1), (1, 'b', 2)]);
+    }
+
+    #[test]
+    fn test_consume_with_observer_stops_at_missing_transition() {
+        let automaton = create_automaton();
+        let mut count = 0;
+
+        assert!(
+            !automaton.consume_with_observer("aab", &mut |_, _, _| count += 1)
+        );
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_consume_path() {
+        let automaton = create_automaton();
+
+        assert_eq!(automaton.consume_path("ab"), Some(vec![0, 1, 2]));
+        assert_eq!(automaton.consume_path("abc"), None);
+        assert_eq!(automaton.consume_path("a"), None);
+    }
+
+    #[test]
+    fn test_consume_from() {
+        let start = State::new(0, false, false);
+        let word = State::new(1, false, false);
+        let number = State::new(2, false, false);
+        let end = State::new(3, true, false);
+
+        let mut automaton = Automaton::new();
+        automaton.add_transition(start, word, "w");
+        automaton.add_transition(start, number, "n");
+        automaton.add_transition(word, end, "a");
+        automaton.add_transition(number, end, "1");
+
+        assert!(automaton.consume_from(&word, "a"));
+        assert!(!automaton.consume_from(&word, "1"));
+        assert!(automaton.consume_from(&number, "1"));
+        assert!(automaton.consume_from(&end, ""));
+        assert!(!automaton.consume_from(&State::new(3, false, false), "a"));
+        assert!(!automaton.consume("a"));
+    }
+
+    #[test]
+    fn test_consume_mapped() {
+        let automaton = Automaton::from_transition_list(
+            "0 letter 1\n1 letter 1\n1 digit 1\nfinal 1",
+        )
+        .unwrap();
+        let classify = |symbol: char| {
+            if symbol.is_ascii_digit() {
+                "digit"
+            } else if symbol.is_alphabetic() {
+                "letter"
+            } else {
+                "other"
+            }
+        };
+
+        assert!(automaton.consume_mapped("x1".chars(), classify));
+        assert!(automaton.consume_mapped("αβ42γ".chars(), classify));
+        assert!(!automaton.consume_mapped("1x".chars(), classify));
+        assert!(!automaton.consume_mapped("a-b".chars(), classify));
+        assert!(automaton.consume_mapped([3, 4], |_| "letter".to_string()));
+    }
+
+    #[test]
+    fn test_transducer() {
+        // Uppercases the letters of an identifier and drops underscores.
+        let start = State::new(0, false, false);
+        let letters = State::new(1, true, false);
+
+        let mut transducer = Transducer::new();
+        for symbol in 'a'..='z' {
+            let output = symbol.to_uppercase().to_string();
+            let symbol = symbol.to_string();
+            transducer.add_transition(start, letters, &symbol, &output);
+            transducer.add_transition(letters, letters, &symbol, &output);
+        }
+        transducer.add_transition(letters, letters, "_", "");
+
+        assert_eq!(
+            transducer.transduce("snake_case"),
+            Some("SNAKECASE".into())
+        );
+        assert_eq!(transducer.transduce("x"), Some("X".into()));
+        assert_eq!(transducer.transduce("_x"), None);
+        assert_eq!(transducer.transduce(""), None);
+    }
+
+    #[test]
+    fn test_coverage_tracker() {
+        let start = State::new(0, false, false);
+        let first = State::new(1, true, false);
+        let second = State::new(2, true, false);
+
+        let mut automaton = Automaton::new();
+        automaton.add_transition(start, first, "a");
+        automaton.add_transition(start, second, "b");
+        automaton.add_transition(first, first, "a");
+
+        let mut tracker = CoverageTracker::new(&automaton);
+        tracker.record_all(&["a", "aa", "c"]);
+        assert_eq!(tracker.uncovered(), vec![(0, "b".to_string())]);
+
+        assert!(tracker.record("b"));
+        assert!(tracker.uncovered().is_empty());
+    }
+
+    #[test]
+    fn test_consume_cow() {
+        use std::borrow::Cow;
+
+        let automaton = create_automaton();
+        let owned: Cow<str> = Cow::Owned("xab".to_string());
+
+        assert!(automaton.consume(Cow::Borrowed("ab")));
+        assert!(automaton.consume(&owned[1..]));
+        assert_eq!(automaton.find(owned), Some(Span::new(1, 3)));
+        assert!(automaton.consume(String::from("ab")));
+    }
+
+    #[test]
+    fn test_incremental_matcher() {
+        let automaton = create_a_plus();
+        let mut matcher = IncrementalMatcher::new(&automaton, "aaaaaa");
+        assert!(matcher.is_match());
+
+        assert_eq!(matcher.relex_range(2, 3, "a"), 1);
+        assert!(matcher.is_match());
+
+        matcher.relex_range(2, 3, "b");
+        assert_eq!(matcher.text(), "aabaaa");
+        assert!(!matcher.is_match());
+        assert_eq!(matcher.state_at(2), Some(1));
+        assert_eq!(matcher.state_at(3), None);
+
+        matcher.relex_range(2, 3, "aa");
+        let full = IncrementalMatcher::new(&automaton, &matcher.text());
+        assert_eq!(matcher.text(), "aaaaaaa");
+        assert_eq!(matcher.states, full.states);
+        assert!(matcher.is_match());
+
+        assert_eq!(matcher.relex_range(2, 4, ""), 0);
+        let full = IncrementalMatcher::new(&automaton, &matcher.text());
+        assert_eq!(matcher.text(), "aaaaa");
+        assert_eq!(matcher.states, full.states);
+        assert!(matcher.is_match());
+
+        matcher.relex_range(0, 5, "");
+        assert!(!matcher.is_match());
+    }
+
+    #[test]
+    fn test_add_range_transition() {
+        let start = State::new(0, false, false);
+        let end = State::new(1, true, false);
+
+        let mut automaton = Automaton::new();
+        automaton
+            .add_range_transition(start, end, 'a', 'z')
+            .unwrap();
+        automaton.add_range_transition(end, end, '0', '9').unwrap();
+
+        for input in ["a", "m", "z", "x0", "q42"] {
+            assert!(automaton.consume(input), "{:?}", input);
+        }
+        for input in ["", "A", "{", "`", "0", "a0b"] {
+            assert!(!automaton.consume(input), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_add_range_transition_empty_range() {
+        let start = State::new(0, false, false);
+        let end = State::new(1, true, false);
+
+        let mut automaton = Automaton::new();
+        assert_eq!(
+            automaton.add_range_transition(start, end, 'z', 'a'),
+            Err(EmptyRange { lo: 'z', hi: 'a' })
+        );
+        assert!(automaton.transition_matrix.symbols().is_empty());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "state 0 already has a transition on \"a\"")]
+    fn test_nondeterministic_transition_is_caught() {
+        let mut automaton = create_automaton();
+        automaton.add_transition(
+            State::new(0, false, false),
+            State::new(2, true, false),
+            "a",
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "state 2 was added with conflicting flags")]
+    fn test_conflicting_state_flags_are_caught() {
+        let mut automaton = create_automaton();
+        automaton.add_transition(
+            State::new(1, false, false),
+            State::new(2, false, false),
+            "c",
+        );
+    }
+
+    #[test]
+    fn test_from_keywords_shares_suffixes() {
+        let automaton =
+            Automaton::from_keywords(&["testing", "running", "jumping"]);
+
+        for keyword in ["testing", "running", "jumping"] {
+            assert!(automaton.consume(keyword), "{:?}", keyword);
+        }
+        for input in ["", "test", "ing", "testingg", "runing", "jumpin"] {
+            assert!(!automaton.consume(input), "{:?}", input);
+        }
+
+        // The start state, three per distinct stem, and one per position of
+        // the shared `ing` suffix including the final state.
+        assert_eq!(automaton.state_count(), 1 + 3 * 3 + 4);
+    }
+
+    #[test]
+    fn test_from_keywords_prefixes_and_empty_keyword() {
+        let automaton = Automaton::from_keywords(&["if", "in", ""]);
+
+        for input in ["", "if", "in"] {
+            assert!(automaton.consume(input), "{:?}", input);
+        }
+        assert!(!automaton.consume("i"));
+        assert_eq!(automaton.state_count(), 3);
+
+        assert!(!Automaton::from_keywords(&[]).consume(""));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let automaton = create_automaton();
+        // Persisted digests rely on this value never changing without a
+        // FINGERPRINT_VERSION bump.
+        assert_eq!(automaton.fingerprint(), 11305089120358126271);
+        assert_eq!(automaton.fingerprint(), create_automaton().fingerprint());
+
+        // Renumbering the states does not change the fingerprint.
+        let renumbered =
+            Automaton::from_transition_list("start 5\nfinal 3\n5 a 7\n7 b 3")
+                .unwrap();
+        assert_eq!(renumbered.fingerprint(), automaton.fingerprint());
+
+        assert_ne!(create_a_plus().fingerprint(), automaton.fingerprint());
+        let other = Automaton::from_transition_list("final 2\n0 a 1\n1 c 2");
+        assert_ne!(other.unwrap().fingerprint(), automaton.fingerprint());
+        let error = Automaton::from_transition_list("error 2\n0 a 1\n1 b 2");
+        assert_ne!(error.unwrap().fingerprint(), automaton.fingerprint());
+    }
+
+    #[test]
+    fn test_until() {
+        let alphabet: Vec<char> = "abc */".chars().collect();
+        let automaton = Automaton::until("/*", "*/", &alphabet);
+
+        for input in ["/**/", "/* a */", "/* * / **/", "/***/"] {
+            assert!(automaton.consume(input), "{:?}", input);
+        }
+        for input in ["/*", "/* a */ */", "/* a", "* a */"] {
+            assert!(!automaton.consume(input), "{:?}", input);
+        }
+
+        let haystack = "/* a */ b /* c */";
+        let spans: Vec<Span> = automaton.find_iter(haystack).collect();
+        assert_eq!(spans, vec![Span::new(0, 7), Span::new(10, 17)]);
+    }
+
+    #[test]
+    fn test_concat() {
+        let a = Automaton::from_transition_list("final 1\n0 a 1").unwrap();
+        let b = Automaton::from_transition_list("final 1\n0 b 1").unwrap();
+
+        let automaton = a.concat(&b);
+        assert!(automaton.consume("ab"));
+        for input in ["", "a", "b", "ba", "abb", "aab"] {
+            assert!(!automaton.consume(input), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_concat_with_overlapping_languages() {
+        // a+ followed by ab: the split point must be guessed.
+        let automaton = create_a_plus()
+            .concat(&create_automaton())
+            .concat(&create_a_plus());
+
+        for input in ["aaba", "aaaabaa"] {
+            assert!(automaton.consume(input), "{:?}", input);
+        }
+        for input in ["aba", "aab", "aaab", "aabab"] {
+            assert!(!automaton.consume(input), "{:?}", input);
+        }
+
+        let empty = Automaton::from_transition_list("final 0").unwrap();
+        assert!(empty.concat(&create_automaton()).consume("ab"));
+        assert!(create_automaton().concat(&empty).consume("ab"));
+    }
+
+    #[test]
+    fn test_intersect_all() {
+        let even_length = Automaton::from_transition_list(
+            "final 0\n0 a 1\n0 b 1\n1 a 0\n1 b 0",
+        )
+        .unwrap();
+        let starts_with_a =
+            Automaton::from_transition_list("final 1\n0 a 1\n1 a 1\n1 b 1")
+                .unwrap();
+        let ends_with_b = Automaton::from_transition_list(
+            "final 1\n0 a 0\n0 b 1\n1 a 0\n1 b 1",
+        )
+        .unwrap();
+        let alphabet = ['a', 'b'];
+
+        let automaton = Automaton::intersect_all(
+            &[&even_length, &starts_with_a, &ends_with_b],
+            &alphabet,
+        );
+
+        for input in strings_over(&alphabet, 6) {
+            let expected = input.len() % 2 == 0
+                && input.starts_with('a')
+                && input.ends_with('b');
+            assert_eq!(automaton.consume(&input), expected, "{:?}", input);
+        }
+
+        let everything = Automaton::intersect_all(&[], &alphabet);
+        assert!(everything.consume("abba"));
+    }
+
+    #[test]
+    fn test_intersect_all_preserves_error_states() {
+        // Both accept `ab`; the first flags a leading `b` as an error, the
+        // second merely rejects it.
+        let flags_b = Automaton::from_transition_list(
+            "final 2\nerror 3\n0 a 1\n1 b 2\n0 b 3\n3 a 3\n3 b 3",
+        )
+        .unwrap();
+        let rejects_b = Automaton::from_transition_list(
+            "final 2\n0 a 1\n1 b 2\n0 b 3\n3 a 3\n3 b 3\n1 a 4",
+        )
+        .unwrap();
+        let ends_in_error = |automaton: &Automaton, input: &str| {
+            let mut is_error = false;
+            automaton.consume_with_observer(input, &mut |_, _, to_state| {
+                is_error = to_state.is_error
+            });
+            is_error
+        };
+
+        let alphabet = ['a', 'b'];
+        let automaton =
+            Automaton::intersect_all(&[&flags_b, &rejects_b], &alphabet);
+
+        assert!(automaton.consume("ab"));
+        for input in ["b", "ba", "bab"] {
+            assert!(ends_in_error(&flags_b, input));
+            assert!(!ends_in_error(&rejects_b, input));
+            assert!(ends_in_error(&automaton, input), "{:?}", input);
+            assert!(!automaton.consume(input));
+        }
+        for input in ["", "a", "abb"] {
+            assert!(!ends_in_error(&automaton, input), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_witnesses() {
+        // A keyword token `if` and an identifier token of x's and i's.
+        let automaton = Automaton::from_transition_list(
+            "final 2 3\n0 i 1\n1 f 2\n0 x 3\n1 x 3\n3 x 3\n3 i 3",
+        )
+        .unwrap();
+
+        assert_eq!(
+            automaton.witnesses(),
+            HashMap::from([(2, "if".to_string()), (3, "x".to_string())])
+        );
+
+        let star = create_a_plus().star();
+        assert_eq!(star.witnesses().get(&0), Some(&String::new()));
+    }
+
+    #[test]
+    fn test_is_exponential_growth() {
+        let a_or_b_star =
+            Automaton::from_transition_list("start 0\nfinal 0\n0 a 0\n0 b 0");
+        assert!(a_or_b_star.unwrap().is_exponential_growth());
+
+        // (aa*b)* has two cycles through state 1.
+        let two_cycles =
+            Automaton::from_transition_list("final 0\n0 a 1\n1 b 0\n1 a 1");
+        assert!(two_cycles.unwrap().is_exponential_growth());
+
+        assert!(!create_a_plus().star().is_exponential_growth());
+        assert!(!create_automaton().is_exponential_growth());
+
+        // a*b* is polynomial: its two cycles share no state.
+        let a_star_b_star =
+            Automaton::from_transition_list("final 0 1\n0 a 0\n0 b 1\n1 b 1");
+        assert!(!a_star_b_star.unwrap().is_exponential_growth());
+
+        // A branching cycle that cannot reach acceptance does not count.
+        let dead_cycle = Automaton::from_transition_list(
+            "final 1\n0 a 1\n0 b 2\n2 a 2\n2 b 2",
+        );
+        assert!(!dead_cycle.unwrap().is_exponential_growth());
+    }
+
+    #[test]
+    fn test_describe() {
+        let a_b_star =
+            Automaton::from_transition_list("final 1\n0 a 1\n1 b 1").unwrap();
+        assert_eq!(a_b_star.describe(), "ab*");
+
+        assert_eq!(create_automaton().describe(), "ab");
+        assert_eq!(create_a_plus().describe(), "a+");
+        assert_eq!(create_a_plus().star().describe(), "a*");
+
+        let a_or_b =
+            Automaton::from_transition_list("final 1\n0 b 1\n0 a 1").unwrap();
+        assert_eq!(a_or_b.describe(), "a|b");
+
+        let optional = Automaton::from_transition_list("final 0 1\n0 a 1");
+        assert_eq!(optional.unwrap().describe(), "a?");
+
+        let branches = Automaton::from_transition_list(
+            "final 3\n0 a 1\n0 b 2\n1 c 3\n2 c 3",
+        );
+        assert_eq!(branches.unwrap().describe(), "(a|b)c");
+
+        let prefix = Automaton::from_transition_list("final 1 2\n0 a 1\n1 b 2");
+        assert_eq!(prefix.unwrap().describe(), "ab?");
+
+        let cycle =
+            Automaton::from_transition_list("final 0\n0 a 1\n1 b 0\n1 a 1");
+        assert_eq!(cycle.unwrap().describe(), "(a(b?a)*b)?");
+
+        assert_eq!(
+            Automaton::from_transition_list("final 0")
+                .unwrap()
+                .describe(),
+            "ε"
+        );
+        assert_eq!(Automaton::new().describe(), "∅");
+    }
+
+    #[test]
+    fn test_add_set_transition() {
+        let start = State::new(0, false, false);
+        let end = State::new(1, true, false);
+
+        let mut automaton = Automaton::new();
+        automaton.add_set_transition(start, end, &['a', 'b', 'c']);
+
+        for input in ["a", "b", "c"] {
+            assert!(automaton.consume(input));
+        }
+        assert!(!automaton.consume("d"));
+        assert!(!automaton.consume("ab"));
+    }
+
+    #[test]
+    fn test_from_transition_list() {
+        let automaton = Automaton::from_transition_list(
+            "# ab or ac, with c marked as an error\n\
+             start 0\n\
+             0 a 1\n\
+             \n\
+             1 b 2\n\
+             1 c 3\n\
+             final 2 3\n\
+             error 3\n",
+        )
+        .unwrap();
+
+        assert!(automaton.consume("ab"));
+        assert!(!automaton.consume("ac"));
+        assert!(!automaton.consume("a"));
+        assert_eq!(
+            automaton.distinguishing_string(0, 1, &['a', 'b']),
+            Some("b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_from_transition_list_start_state() {
+        let automaton =
+            Automaton::from_transition_list("start 1\n1 a 0\nfinal 0").unwrap();
+
+        assert!(automaton.consume("a"));
+        assert!(!automaton.consume(""));
+    }
+
+    #[test]
+    fn test_from_transition_list_errors() {
+        assert_eq!(
+            Automaton::from_transition_list("0 a 1\n0 a").err(),
+            Some(ParseError::InvalidLine(2))
+        );
+        assert_eq!(
+            Automaton::from_transition_list("final x").err(),
+            Some(ParseError::InvalidLine(1))
+        );
+        assert_eq!(
+            Automaton::from_transition_list("0 a 1\n0 a 2").err(),
+            Some(ParseError::NondeterministicTransition(2))
+        );
+    }
+
+    #[test]
+    fn test_distinguishing_string() {
+        let automaton = create_automaton();
+        assert_eq!(
+            automaton.distinguishing_string(0, 1, &['a', 'b']),
+            Some("b".to_string())
+        );
+        assert_eq!(
+            automaton.distinguishing_string(1, 2, &['a', 'b']),
+            Some("".to_string())
+        );
+    }
+
+    #[test]
+    fn test_distinguishing_string_equivalent_states() {
+        let start = State::new(0, false, false);
+        let first = State::new(1, true, false);
+        let second = State::new(2, true, false);
+
+        let mut automaton = Automaton::new();
+        automaton.add_transition(start, first, "a");
+        automaton.add_transition(start, second, "b");
+        automaton.add_transition(first, first, "a");
+        automaton.add_transition(second, second, "a");
+
+        assert_eq!(automaton.distinguishing_string(1, 2, &['a', 'b']), None);
+        assert_eq!(automaton.distinguishing_string(2, 2, &['a', 'b']), None);
+    }
+}
+
+pub mod nfa {
+    use std::borrow::Borrow;
+    use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+    use std::hash::{Hash, Hasher};
+
+    use super::{StateIndex, Symbol};
+    use crate::span::Span;
+
+    const EPSILON: &str = "ε";
+    const COMMENT_START: &str = "(?#";
+    const CASE_INSENSITIVE: &str = "(?i)";
+
+    // Simple case foldings that can't be recovered from a one-to-one
+    // `to_lowercase` of the character or `to_uppercase` of its folding.
+    const SPECIAL_FOLDS: [(char, char); 13] = [
+        ('\u{00B5}', 'μ'),
+        ('\u{017F}', 's'),
+        ('\u{0345}', 'ι'),
+        ('ς', 'σ'),
+        ('ϐ', 'β'),
+        ('ϑ', 'θ'),
+        ('ϕ', 'φ'),
+        ('ϖ', 'π'),
+        ('ϰ', 'κ'),
+        ('ϱ', 'ρ'),
+        ('\u{1FBE}', 'ι'),
+        ('\u{1E9E}', 'ß'),
+        ('\u{212A}', 'k'),
+    ];
+
+    // Folds with Unicode simple case folding. Full foldings that change the
+    // length of the string (e.g. `ß` to `ss`) are not applied.
+    fn fold_char(character: char) -> char {
+        if let Some(&(_, folded)) = SPECIAL_FOLDS
+            .iter()
+            .find(|&&(special, _)| special == character)
+        {
+            return folded;
+        }
+
+        let mut lowercase = character.to_lowercase();
+        match (lowercase.next(), lowercase.next()) {
+            (Some(folded), None) => folded,
+            _ => character,
+        }
+    }
+
+    // All characters with the same simple case folding as `character`.
+    fn case_variants(character: char) -> Vec<char> {
+        let folded = fold_char(character);
+        let mut uppercase = folded.to_uppercase();
+        let upper = match (uppercase.next(), uppercase.next()) {
+            (Some(upper), None) => Some(upper),
+            _ => None,
+        };
+
+        let mut variants: Vec<char> = [character, folded]
+            .into_iter()
+            .chain(upper)
+            .chain(
+                SPECIAL_FOLDS
+                    .iter()
+                    .filter(|&&(_, special)| special == folded)
+                    .map(|&(special, _)| special),
+            )
+            .filter(|&variant| fold_char(variant) == folded)
+            .collect();
+        variants.sort();
+        variants.dedup();
+
+        variants
+    }
+
+    // Decodes the escapes accepted by `from_regex_escaped`; errors carry
+    // the byte offset of the offending backslash.
+    fn unescape(pattern: &str) -> Result<Vec<char>, EscapeError> {
+        let mut symbols = Vec::new();
+        let mut rest = pattern;
+
+        while let Some(character) = rest.chars().next() {
+            let position = pattern.len() - rest.len();
+            rest = &rest[character.len_utf8()..];
+            if character != '\\' {
+                symbols.push(character);
+                continue;
+            }
+
+            let (digits, tail) = if let Some(tail) = rest.strip_prefix('x') {
+                match (tail.get(..2), tail.get(2..)) {
+                    (Some(digits), Some(tail)) => (digits, tail),
+                    _ => return Err(EscapeError::Malformed(position)),
+                }
+            } else if let Some(tail) = rest.strip_prefix("u{") {
+                match tail.split_once('}') {
+                    Some((digits, tail)) if digits.len() <= 6 => (digits, tail),
+                    _ => return Err(EscapeError::Malformed(position)),
+                }
+            } else {
+                if let Some(tail) = rest.strip_prefix('\\') {
+                    rest = tail;
+                }
+                symbols.push(character);
+                continue;
+            };
+
+            if digits.is_empty()
+                || !digits.chars().all(|digit| digit.is_ascii_hexdigit())
+            {
+                return Err(EscapeError::Malformed(position));
+            }
+            let code_point = u32::from_str_radix(digits, 16).unwrap();
+            match char::from_u32(code_point) {
+                None => return Err(EscapeError::InvalidCodePoint(code_point)),
+                Some(symbol) => symbols.push(symbol),
+            }
+            rest = tail;
+        }
+
+        Ok(symbols)
+    }
+
+    // Removes `(?#...)` comments; an unterminated comment runs to the end.
+    fn strip_comments(regex_str: &str) -> String {
+        let mut stripped = String::new();
+        let mut rest = regex_str;
+
+        while let Some(start) = rest.find(COMMENT_START) {
+            stripped.push_str(&rest[..start]);
+            rest = match rest[start..].find(')') {
+                None => "",
+                Some(end) => &rest[start + end + 1..],
+            };
+        }
+        stripped.push_str(rest);
+
+        stripped
+    }
+
+    #[derive(Clone)]
+    pub struct Automaton {
+        pub regex_str: String,
+        pub start_state: State,
+        states: Vec<State>,
+        transition_matrix: TransitionMatrix,
+    }
+
+    impl Automaton {
+        /// Builds the concatenation of the characters of `regex_str`. A
+        /// leading `(?i)` makes the match case-insensitive under Unicode
+        /// simple case folding.
+        pub fn from_regex(regex_str: &str) -> Automaton {
+            let regex_str = strip_comments(regex_str);
+            if let Some(pattern) = regex_str.strip_prefix(CASE_INSENSITIVE) {
+                let mut new = Automaton::from_regex(pattern).fold_case();
+                new.regex_str = regex_str;
+                return new;
+            }
+
+            let symbols: Vec<char> = regex_str.chars().collect();
+            let mut new = Automaton::from_symbols(&symbols);
+            new.regex_str = regex_str;
+
+            new
+        }
+
+        /// Like `from_regex`, but `\xHH` and `\u{H...}` stand for the char
+        /// with that code point and `\\` for a backslash; any other
+        /// backslash is literal.
+        pub fn from_regex_escaped(
+            regex_str: &str,
+        ) -> Result<Automaton, EscapeError> {
+            let regex_str = strip_comments(regex_str);
+            let mut new = match regex_str.strip_prefix(CASE_INSENSITIVE) {
+                Some(pattern) => {
+                    Automaton::from_symbols(&unescape(pattern)?).fold_case()
+                }
+                None => Automaton::from_symbols(&unescape(&regex_str)?),
+            };
+            new.regex_str = regex_str;
+
+            Ok(new)
+        }
+
+        fn from_symbols(symbols: &[char]) -> Automaton {
+            if symbols.is_empty() {
+                return Automaton::from_label(Label::Epsilon);
+            }
+
+            // Each symbol gets a pair of states, linked to the next pair by
+            // an epsilon transition.
+            let mut new = Automaton::with_capacity(2 * symbols.len());
+            for (index, symbol) in symbols.iter().enumerate() {
+                let start = new.add_state(false);
+                let end = new.add_state(index == symbols.len() - 1);
+
+                new.add_symbol(start, end, &symbol.to_string());
+                if index > 0 {
+                    new.add_epsilon(start - 1, start);
+                }
+            }
+
+            new
+        }
+
+        /// Like `from_regex`, but unescaped whitespace is ignored and `#`
+        /// starts a comment running to the end of the line. `\ ` and `\#`
+        /// stand for a literal space and `#`, and whitespace inside `[...]`
+        /// is kept.
+        pub fn from_regex_extended(regex_str: &str) -> Automaton {
+            let mut compact = String::new();
+            let mut chars = regex_str.chars();
+            let mut in_class = false;
+
+            while let Some(character) = chars.next() {
+                match character {
+                    '\\' => match chars.next() {
+                        Some(escaped)
+                            if escaped.is_whitespace() || escaped == '#' =>
+                        {
+                            compact.push(escaped)
+                        }
+                        Some(escaped) => {
+                            compact.push(character);
+                            compact.push(escaped);
+                        }
+                        None => compact.push(character),
+                    },
+                    '[' | ']' => {
+                        in_class = character == '[';
+                        compact.push(character);
+                    }
+                    _ if in_class => compact.push(character),
+                    '#' => {
+                        chars.by_ref().find(|&character| character == '\n');
+                    }
+                    _ if character.is_whitespace() => {}
+                    _ => compact.push(character),
+                }
+            }
+
+            Automaton::from_regex(&compact)
+        }
+
+        /// Like `from_regex`, but fails if the pattern references a symbol
+        /// outside the declared input `alphabet`.
+        pub fn from_regex_over(
+            regex_str: &str,
+            alphabet: &[char],
+        ) -> Result<Automaton, AlphabetError> {
+            let pattern = strip_comments(regex_str);
+            let pattern =
+                pattern.strip_prefix(CASE_INSENSITIVE).unwrap_or(&pattern);
+
+            match pattern.chars().find(|symbol| !alphabet.contains(symbol)) {
+                None => Ok(Automaton::from_regex(regex_str)),
+                Some(symbol) => {
+                    Err(AlphabetError::SymbolOutsideAlphabet(symbol))
+                }
+            }
+        }
+
+        /// Builds the automaton for `regex_str` and checks that it accepts
+        /// every one of `positives` and rejects every one of `negatives`.
+        pub fn compile_and_verify(
+            regex_str: &str,
+            positives: &[&str],
+            negatives: &[&str],
+        ) -> Result<Automaton, VerificationError> {
+            let automaton = Automaton::from_regex(regex_str);
+
+            let error = VerificationError {
+                rejected_positives: positives
+                    .iter()
+                    .filter(|input| !automaton.matches(input))
+                    .map(|input| input.to_string())
+                    .collect(),
+                accepted_negatives: negatives
+                    .iter()
+                    .filter(|input| automaton.matches(input))
+                    .map(|input| input.to_string())
+                    .collect(),
+            };
+
+            if error.rejected_positives.is_empty()
+                && error.accepted_negatives.is_empty()
+            {
+                Ok(automaton)
+            } else {
+                Err(error)
+            }
+        }
+
+        pub fn from_regex_explicit(
+            regex_str: &str,
+            operator: char,
+        ) -> Automaton {
+            let mut labels = regex_str.split(operator).map(|symbol| {
+                if symbol.is_empty() {
+                    Label::Epsilon
+                } else {
+                    Label::Symbol(symbol.to_string())
+                }
+            });
+
+            let first = Automaton::from_label(labels.next().unwrap());
+            let mut new = labels.fold(first, |new, label| {
+                new.append(&Automaton::from_label(label))
+            });
+            new.regex_str = regex_str.to_string();
+
+            new
+        }
+
+        /// Builds an automaton from the productions of a right-linear grammar,
+        /// whose start symbol is the left-hand side of the first rule.
+        pub fn from_right_linear(
+            rules: &[(NonTerminal, Production)],
+        ) -> Result<Automaton, GrammarError> {
+            let mut numbers: HashMap<NonTerminal, StateIndex> = HashMap::new();
+            for (non_terminal, _) in rules.iter() {
+                let number = numbers.len();
+                numbers.entry(non_terminal).or_insert(number);
+            }
+
+            let start = match rules.first() {
+                None => return Err(GrammarError::NoRules),
+                Some((non_terminal, _)) => {
+                    State::new(numbers[non_terminal], false)
+                }
+            };
+            let mut new = Automaton {
+                regex_str: String::new(),
+                start_state: start,
+                states: Vec::new(),
+                transition_matrix: TransitionMatrix::new(),
+            };
+            for number in numbers.values() {
+                new.register(&State::new(*number, false));
+            }
+            let end = new.add_state(true);
+
+            for (non_terminal, production) in rules.iter() {
+                let from_state = numbers[non_terminal];
+
+                match production {
+                    Production::Step(symbol, next) => {
+                        let to_state = match numbers.get(next) {
+                            None => {
+                                return Err(GrammarError::UndefinedNonTerminal(
+                                    next.to_string(),
+                                ))
+                            }
+                            Some(&to_state) => to_state,
+                        };
+                        new.add_symbol(from_state, to_state, symbol);
+                    }
+                    Production::Terminal(symbol) => {
+                        new.add_symbol(from_state, end, symbol);
+                    }
+                    Production::Empty => {
+                        new.states[from_state].is_final = true;
+                    }
+                }
+            }
+            new.start_state = new.states[new.start_state.number].clone();
+
+            Ok(new)
+        }
+
+        pub fn from_char(character: &str) -> Automaton {
+            Automaton::from_label(Label::Symbol(character.to_string()))
+        }
+
+        pub fn concatenate(&self, regex_str: &str) -> Automaton {
+            let other = Automaton::from_regex(regex_str);
+            let mut new = self.append(&other);
+
+            new.regex_str = self.regex_str.clone() + regex_str;
+            new.debug_check_invariants();
+
+            new
+        }
+
+        pub fn concatenate_with(&self, other: &Automaton) -> Automaton {
+            let mut new = self.append(other);
+
+            new.regex_str = self.regex_str.clone() + &other.regex_str;
+            new.debug_check_invariants();
+
+            new
+        }
+
+        /// Wraps the automaton in capture group `index`, tagging the epsilon
+        /// transitions that enter and leave it.
+        pub fn group(&self, index: usize) -> Automaton {
+            let mut new = self.clone();
+
+            let ends: Vec<StateIndex> =
+                new.end_states().map(|state| state.number).collect();
+            let end = new.add_state(true);
+            for old_end in ends {
+                new.states[old_end].is_final = false;
+                new.add_epsilon(old_end, end);
+                new.add_tag(old_end, end, Tag::Close(index));
+            }
+
+            let old_start = new.start_state.number;
+            new.insert_start();
+            new.add_tag(new.start_state.number, old_start, Tag::Open(index));
+
+            new.regex_str = format!("({})", self.regex_str);
+            new.debug_check_invariants();
+
+            new
+        }
+
+        pub fn union(&self, regex_str: &str) -> Automaton {
+            let other = Automaton::from_regex(regex_str);
+            let mut new = self.add(&other);
+
+            new.append_final();
+            new.regex_str = self.regex_str.clone() + "|" + regex_str;
+            new.debug_check_invariants();
+
+            new
+        }
+
+        pub fn kleene_closure(&self) -> Automaton {
+            let mut new = self.clone();
+
+            let end = new.loop_back();
+            new.insert_start();
+            new.add_epsilon(new.start_state.number, end);
+
+            new.regex_str = self.grouped_regex_str() + "*";
+            new.debug_check_invariants();
+
+            new
+        }
+
+        pub fn positive_closure(&self) -> Automaton {
+            let mut new = self.clone();
+
+            new.loop_back();
+            new.insert_start();
+
+            new.regex_str = self.grouped_regex_str() + "+";
+            new.debug_check_invariants();
+
+            new
+        }
+
+        pub fn optional(&self) -> Automaton {
+            let mut new = self.clone();
+
+            new.append_final();
+            let end = new.end_states().next().unwrap().number;
+            new.insert_start();
+            new.add_epsilon(new.start_state.number, end);
+
+            new.regex_str = self.grouped_regex_str() + "?";
+            new.debug_check_invariants();
+
+            new
+        }
+
+        /// Builds the shuffle product: every interleaving of a string of
+        /// `self` with a string of `other`. Each product state pairs a state
+        /// of both automata, and each transition advances exactly one side.
+        pub fn shuffle(&self, other: &Automaton) -> Automaton {
+            let width = other.states.len();
+            let pair = |state: StateIndex, other_state: StateIndex| {
+                state * width + other_state
+            };
+
+            let mut new = Automaton::with_capacity(self.states.len() * width);
+            for state in self.states.iter() {
+                for other_state in other.states.iter() {
+                    new.add_state(state.is_final && other_state.is_final);
+                }
+            }
+            new.start_state = new.states
+                [pair(self.start_state.number, other.start_state.number)]
+            .clone();
+
+            for (from_state, label, to_state) in self.transition_matrix.iter() {
+                for other_state in 0..width {
+                    new.add_label(
+                        pair(from_state, other_state),
+                        pair(to_state, other_state),
+                        label.clone(),
+                    );
+                }
+            }
+            for (from_state, label, to_state) in other.transition_matrix.iter()
+            {
+                for state in 0..self.states.len() {
+                    new.add_label(
+                        pair(state, from_state),
+                        pair(state, to_state),
+                        label.clone(),
+                    );
+                }
+            }
+
+            new.debug_check_invariants();
+
+            new
+        }
+
+        pub fn epsilon_closure(
+            &self,
+            states: &HashSet<StateIndex>,
+        ) -> HashSet<StateIndex> {
+            let mut closure = states.clone();
+            let mut stack: Vec<StateIndex> = states.iter().copied().collect();
+
+            while let Some(state) = stack.pop() {
+                for target in
+                    self.transition_matrix.targets(state, &Label::Epsilon)
+                {
+                    if closure.insert(target) {
+                        stack.push(target);
+                    }
+                }
+            }
+
+            closure
+        }
+
+        pub fn start_states(&self) -> HashSet<StateIndex> {
+            self.epsilon_closure(&HashSet::from([self.start_state.number]))
+        }
+
+        pub fn step(
+            &self,
+            states: &HashSet<StateIndex>,
+            symbol: &str,
+        ) -> HashSet<StateIndex> {
+            let next = states
+                .iter()
+                .flat_map(|&state| {
+                    self.transition_matrix.targets(state, &Some(symbol))
+                })
+                .collect();
+
+            self.epsilon_closure(&next)
+        }
+
+        /// Returns a shortest string over `alphabet` accepted by exactly one
+        /// of `self` and `other`, or `None` if no such string exists.
+        pub fn difference_witness(
+            &self,
+            other: &Automaton,
+            alphabet: &[char],
+        ) -> Option<String> {
+            let key = |states: &HashSet<StateIndex>| -> BTreeSet<StateIndex> {
+                states.iter().copied().collect()
+            };
+            let mut visited = HashSet::new();
+            let mut queue = VecDeque::new();
+
+            let start = (self.start_states(), other.start_states());
+            visited.insert((key(&start.0), key(&start.1)));
+            queue.push_back((start.0, start.1, String::new()));
+
+            while let Some((current, other_current, witness)) =
+                queue.pop_front()
+            {
+                if self.accepts_any(&current)
+                    != other.accepts_any(&other_current)
+                {
+                    return Some(witness);
+                }
+
+                for symbol in alphabet {
+                    let mut buffer = [0; 4];
+                    let symbol_str = &*symbol.encode_utf8(&mut buffer);
+                    let next = self.step(&current, symbol_str);
+                    let other_next = other.step(&other_current, symbol_str);
+                    if (next.is_empty() && other_next.is_empty())
+                        || !visited.insert((key(&next), key(&other_next)))
+                    {
+                        continue;
+                    }
+
+                    let mut next_witness = witness.clone();
+                    next_witness.push(*symbol);
+                    queue.push_back((next, other_next, next_witness));
+                }
+            }
+
+            None
+        }
+
+        /// Reports whether `self` and `other` accept the same strings over
+        /// `alphabet`.
+        pub fn is_equivalent(
+            &self,
+            other: &Automaton,
+            alphabet: &[char],
+        ) -> bool {
+            self.difference_witness(other, alphabet).is_none()
+        }
+
+        /// Returns the span of every capture group, indexed by group, if
+        /// `input` matches. Threads are explored in order of state number,
+        /// so an ambiguous pattern yields one of its valid assignments.
+        pub fn captures(
+            &self,
+            input: impl AsRef<str>,
+        ) -> Option<Vec<Option<Span>>> {
+            let input = input.as_ref();
+            let slots = self
+                .transition_matrix
+                .tags
+                .values()
+                .flatten()
+                .map(|tag| 2 * (tag.group() + 1))
+                .max()
+                .unwrap_or(0);
+
+            let mut current = self.tagged_closure(
+                vec![(self.start_state.number, vec![None; slots])],
+                0,
+            );
+            for (position, symbol) in input.char_indices() {
+                let mut buffer = [0; 4];
+                let label = Some(&*symbol.encode_utf8(&mut buffer));
+                let next = current
+                    .iter()
+                    .flat_map(|(state, slots)| {
+                        let mut targets: Vec<StateIndex> = self
+                            .transition_matrix
+                            .targets(*state, &label)
+                            .collect();
+                        targets.sort();
+                        targets
+                            .into_iter()
+                            .map(|target| (target, slots.clone()))
+                    })
+                    .collect();
+
+                current =
+                    self.tagged_closure(next, position + symbol.len_utf8());
+                if current.is_empty() {
+                    return None;
+                }
+            }
+
+            let (_, slots) = current
+                .into_iter()
+                .find(|(state, _)| self.states[*state].is_final)?;
+
+            Some(
+                slots
+                    .chunks(2)
+                    .map(|pair| match (pair[0], pair[1]) {
+                        (Some(start), Some(end)) => Some(Span::new(start, end)),
+                        _ => None,
+                    })
+                    .collect(),
+            )
+        }
+
+        /// Returns the tagged epsilon transitions as `(from, tag, to)`.
+        pub fn tagged_transitions(&self) -> Vec<(StateIndex, Tag, StateIndex)> {
+            let mut tagged: Vec<(StateIndex, Tag, StateIndex)> = self
+                .transition_matrix
+                .tags
+                .iter()
+                .flat_map(|(&(from_state, to_state), tags)| {
+                    tags.iter().map(move |&tag| (from_state, tag, to_state))
+                })
+                .collect();
+            tagged.sort();
+
+            tagged
+        }
+
+        /// Returns the literal spelled by every path through the automaton,
+        /// if there is exactly one, so that callers can compare or search for
+        /// it directly instead of simulating the automaton.
+        pub fn as_literal(&self) -> Option<String> {
+            let mut literal = String::new();
+            let mut current = self.start_states();
+            let mut visited = HashSet::new();
+
+            loop {
+                let mut key: Vec<StateIndex> =
+                    current.iter().copied().collect();
+                key.sort();
+                if !visited.insert(key) {
+                    return None;
+                }
+
+                let mut symbols: Vec<&str> = current
+                    .iter()
+                    .flat_map(|&state| self.transition_matrix.labels(state))
+                    .filter_map(|label| match label {
+                        Label::Epsilon => None,
+                        Label::Symbol(symbol) => Some(symbol.as_str()),
+                    })
+                    .collect();
+                symbols.sort();
+                symbols.dedup();
+
+                if self.accepts_any(&current) {
+                    return symbols.is_empty().then_some(literal);
+                }
+                let symbol = match symbols.as_slice() {
+                    [symbol] => *symbol,
+                    _ => return None,
+                };
+
+                literal.push_str(symbol);
+                current = self.step(&current, symbol);
+            }
+        }
+
+        /// Determinizes the automaton by subset construction, giving up once
+        /// more than `max_states` states would be needed, so that callers
+        /// can fall back to simulating the NFA.
+        pub fn to_dfa_bounded(
+            &self,
+            max_states: usize,
+        ) -> Result<super::Automaton, Nondeterminizable> {
+            let mut symbols: Vec<&str> = self
+                .transition_matrix
+                .iter()
+                .filter_map(|(_, label, _)| match label {
+                    Label::Epsilon => None,
+                    Label::Symbol(symbol) => Some(symbol.as_str()),
+                })
+                .collect();
+            symbols.sort();
+            symbols.dedup();
+
+            let key = |states: &HashSet<StateIndex>| -> BTreeSet<StateIndex> {
+                states.iter().copied().collect()
+            };
+            let dfa_state =
+                |number: StateIndex, states: &HashSet<StateIndex>| {
+                    super::State::new(number, self.accepts_any(states), false)
+                };
+
+            if max_states == 0 {
+                return Err(Nondeterminizable { max_states });
+            }
+
+            let start = self.start_states();
+            let mut numbers = HashMap::from([(key(&start), 0)]);
+            let mut subsets = vec![start];
+            let mut dfa = super::Automaton::new();
+            dfa.transition_matrix
+                .set_start_state(dfa_state(0, &subsets[0]));
+
+            let mut number = 0;
+            while number < subsets.len() {
+                let from_state = dfa_state(number, &subsets[number]);
+
+                for symbol in symbols.iter() {
+                    let targets = self.step(&subsets[number], symbol);
+                    if targets.is_empty() {
+                        continue;
+                    }
+
+                    let target_number = match numbers.get(&key(&targets)) {
+                        Some(&target_number) => target_number,
+                        None if subsets.len() >= max_states => {
+                            return Err(Nondeterminizable { max_states });
+                        }
+                        None => {
+                            numbers.insert(key(&targets), subsets.len());
+                            subsets.push(targets.clone());
+                            subsets.len() - 1
+                        }
+                    };
+                    let to_state = dfa_state(target_number, &targets);
+
+                    dfa.add_transition(from_state, to_state, symbol);
+                }
+
+                number += 1;
+            }
+
+            Ok(dfa)
+        }
+
+        pub fn matches(&self, input: impl AsRef<str>) -> bool {
+            let mut current = self.start_states();
+
+            for symbol in input.as_ref().chars() {
+                current = self.step(&current, symbol.encode_utf8(&mut [0; 4]));
+                if current.is_empty() {
+                    return false;
+                }
+            }
+
+            self.accepts_any(&current)
+        }
+
+        /// Returns the span of the leftmost-longest match in `haystack`,
+        /// simulating a fresh thread from every position.
+        pub fn find(&self, haystack: impl AsRef<str>) -> Option<Span> {
+            let haystack = haystack.as_ref();
+            // Maps each live state to the leftmost position its thread
+            // started from.
+            let mut current: HashMap<StateIndex, usize> = HashMap::new();
+            let mut found: Option<Span> = None;
+
+            let positions = haystack
+                .char_indices()
+                .map(|(position, symbol)| (position, Some(symbol)))
+                .chain([(haystack.len(), None)]);
+
+            for (position, symbol) in positions {
+                if found.is_none() {
+                    for state in self.start_states() {
+                        current.entry(state).or_insert(position);
+                    }
+                }
+
+                let accepted = current
+                    .iter()
+                    .filter(|(&state, _)| self.states[state].is_final)
+                    .map(|(_, &start)| start)
+                    .min();
+                if let Some(start) = accepted {
+                    if found.is_none_or(|found| start <= found.start) {
+                        found = Some(Span::new(start, position));
+                    }
+                }
+                if let Some(found) = found {
+                    current.retain(|_, start| *start <= found.start);
+                }
+
+                let Some(symbol) = symbol else { break };
+                let mut buffer = [0; 4];
+                let symbol = &*symbol.encode_utf8(&mut buffer);
+                let mut next: HashMap<StateIndex, usize> = HashMap::new();
+                for (&state, &start) in current.iter() {
+                    let states = HashSet::from([state]);
+                    for target in self.step(&states, symbol) {
+                        let entry = next.entry(target).or_insert(start);
+                        *entry = (*entry).min(start);
+                    }
+                }
+                current = next;
+
+                if current.is_empty() && found.is_some() {
+                    break;
+                }
+            }
+
+            found
+        }
+
+        pub fn add_transition(
+            &mut self,
+            from_state: &State,
+            to_state: &State,
+            symbol: &str,
+        ) {
+            self.register(from_state);
+            self.register(to_state);
+            self.transition_matrix
+                .add_transition(from_state, to_state, symbol);
+        }
+
+        pub fn add_epsilon_transition(
+            &mut self,
+            from_state: &State,
+            to_state: &State,
+        ) {
+            self.register(from_state);
+            self.register(to_state);
+            self.transition_matrix
+                .add_epsilon_transition(from_state, to_state);
+        }
+
+        /// Checks that no state has an epsilon transition to itself, and
+        /// returns `Err(states)` with the offending states in order
+        /// otherwise. Longer epsilon cycles, such as the loop of `(|)*`, are
+        /// left alone: the epsilon closure terminates on them regardless.
+        pub fn check_no_epsilon_self_loops(
+            &self,
+        ) -> Result<(), Vec<StateIndex>> {
+            let mut states: Vec<StateIndex> = self
+                .transition_matrix
+                .iter()
+                .filter(|&(from_state, label, to_state)| {
+                    *label == Label::Epsilon && from_state == to_state
+                })
+                .map(|(from_state, _, _)| from_state)
+                .collect();
+
+            if states.is_empty() {
+                return Ok(());
+            }
+
+            states.sort();
+            Err(states)
+        }
+
+        pub fn transition_count(&self) -> usize {
+            self.transition_matrix.iter().count()
+        }
+
+        pub fn epsilon_count(&self) -> usize {
+            self.transition_matrix
+                .iter()
+                .filter(|(_, label, _)| **label == Label::Epsilon)
+                .count()
+        }
+
+        pub fn states(&self) -> &[State] {
+            &self.states
+        }
+
+        pub fn transitions(&self) -> impl Iterator<Item = Transition<'_>> {
+            self.transition_matrix.iter().map(
+                |(from_state, label, to_state)| {
+                    Transition::new(
+                        &self.states[from_state],
+                        &self.states[to_state],
+                        label.clone(),
+                    )
+                },
+            )
+        }
+
+        fn register(&mut self, state: &State) {
+            while self.states.len() <= state.number {
+                let number = self.states.len();
+                self.states.push(State::new(number, false));
+            }
+
+            if state.is_final {
+                self.states[state.number].is_final = true;
+            }
+        }
+
+        fn add_state(&mut self, is_final: bool) -> StateIndex {
+            let state = State::new(self.states.len(), is_final);
+            let number = state.number;
+            self.states.push(state);
+
+            number
+        }
+
+        fn with_capacity(states: usize) -> Automaton {
+            Automaton {
+                regex_str: String::new(),
+                start_state: State::new(0, false),
+                states: Vec::with_capacity(states),
+                transition_matrix: TransitionMatrix::with_capacity(states),
+            }
+        }
+
+        fn from_label(label: Label) -> Automaton {
+            let start = State::new(0, false);
+            let end = State::new(1, true);
+
+            let mut new = Automaton {
+                regex_str: match &label {
+                    Label::Epsilon => String::new(),
+                    Label::Symbol(symbol) => symbol.clone(),
+                },
+                start_state: start.clone(),
+                states: vec![start, end],
+                transition_matrix: TransitionMatrix::new(),
+            };
+            new.add_label(0, 1, label);
+
+            new
+        }
+
+        fn add_label(
+            &mut self,
+            from_state: StateIndex,
+            to_state: StateIndex,
+            label: Label,
+        ) {
+            self.transition_matrix.add(
+                &self.states[from_state],
+                &self.states[to_state],
+                label,
+            );
+        }
+
+        fn add_symbol(
+            &mut self,
+            from_state: StateIndex,
+            to_state: StateIndex,
+            symbol: &str,
+        ) {
+            self.add_label(
+                from_state,
+                to_state,
+                Label::Symbol(symbol.to_string()),
+            );
+        }
+
+        fn add_epsilon(
+            &mut self,
+            from_state: StateIndex,
+            to_state: StateIndex,
+        ) {
+            // An epsilon self-loop never changes the language but trips up
+            // closure computations, so construction never creates one.
+            if from_state == to_state {
+                return;
+            }
+
+            self.add_label(from_state, to_state, Label::Epsilon);
+        }
+
+        // Copies the states and transitions of `other` into `self`, shifting
+        // their numbers past the existing states; returns the shift.
+        fn merge(&mut self, other: &Automaton) -> StateIndex {
+            let offset = self.states.len();
+
+            for state in other.states.iter() {
+                self.states
+                    .push(State::new(offset + state.number, state.is_final));
+            }
+            for (from_state, label, to_state) in other.transition_matrix.iter()
+            {
+                self.add_label(
+                    offset + from_state,
+                    offset + to_state,
+                    label.clone(),
+                );
+            }
+            for (&(from_state, to_state), tags) in
+                other.transition_matrix.tags.iter()
+            {
+                for &tag in tags {
+                    self.add_tag(offset + from_state, offset + to_state, tag);
+                }
+            }
+
+            offset
+        }
+
+        fn append(&self, other: &Automaton) -> Automaton {
+            let mut new = self.clone();
+            let ends: Vec<StateIndex> =
+                new.end_states().map(|state| state.number).collect();
+            let offset = new.merge(other);
+
+            for end in ends {
+                new.states[end].is_final = false;
+                new.add_epsilon(end, offset + other.start_state.number);
+            }
+
+            new
+        }
+
+        fn add(&self, other: &Automaton) -> Automaton {
+            let mut new = self.clone();
+            let offset = new.merge(other);
+
+            new.insert_start();
+            new.add_epsilon(
+                new.start_state.number,
+                offset + other.start_state.number,
+            );
+
+            new
+        }
+
+        fn append_final(&mut self) {
+            let ends: Vec<StateIndex> =
+                self.end_states().map(|state| state.number).collect();
+            let end = self.add_state(true);
+
+            for old_end in ends {
+                self.states[old_end].is_final = false;
+                self.add_epsilon(old_end, end);
+            }
+        }
+
+        fn insert_start(&mut self) {
+            let old_start = self.start_state.number;
+            let start = self.add_state(false);
+
+            self.start_state = self.states[start].clone();
+            self.add_epsilon(start, old_start);
+        }
+
+        // Adds transitions on every case variant of each single-character
+        // symbol.
+        fn fold_case(&self) -> Automaton {
+            let mut new = self.clone();
+
+            for (from_state, label, to_state) in self.transition_matrix.iter() {
+                let mut chars = match label {
+                    Label::Epsilon => continue,
+                    Label::Symbol(symbol) => symbol.chars(),
+                };
+                let character = match (chars.next(), chars.next()) {
+                    (Some(character), None) => character,
+                    _ => continue,
+                };
+
+                for variant in case_variants(character) {
+                    new.add_symbol(from_state, to_state, &variant.to_string());
+                }
+            }
+
+            new
+        }
+
+        // Funnels the final states into a single new final state with an
+        // epsilon transition back to the start; returns the new final state.
+        fn loop_back(&mut self) -> StateIndex {
+            self.append_final();
+            let end = self.end_states().next().unwrap().number;
+            self.add_epsilon(end, self.start_state.number);
+
+            end
+        }
+
+        fn grouped_regex_str(&self) -> String {
+            let is_atomic = self.regex_str.chars().count() == 1
+                && !"|*+?".contains(self.regex_str.as_str());
+
+            if is_atomic {
+                self.regex_str.clone()
+            } else {
+                format!("({})", self.regex_str)
+            }
+        }
+
+        // Checks, in debug builds only, that the start state and every
+        // transition refer to registered states.
+        fn debug_check_invariants(&self) {
+            debug_assert!(
+                self.start_state.number < self.states.len(),
+                "start state s{} is not registered",
+                self.start_state.number
+            );
+            for (from_state, _, to_state) in self.transition_matrix.iter() {
+                debug_assert!(
+                    from_state.max(to_state) < self.states.len(),
+                    "transition s{} -> s{} refers to an unregistered state",
+                    from_state,
+                    to_state
+                );
+            }
+        }
+
+        // Follows epsilon transitions from each thread, recording the
+        // position in the capture slots of every tag passed; the first
+        // thread to reach a state keeps it.
+        fn tagged_closure(
+            &self,
+            threads: Vec<(StateIndex, Vec<Option<usize>>)>,
+            position: usize,
+        ) -> Vec<(StateIndex, Vec<Option<usize>>)> {
+            let mut visited = HashSet::new();
+            let mut closure = Vec::new();
+            let mut stack: Vec<(StateIndex, Vec<Option<usize>>)> =
+                threads.into_iter().rev().collect();
+
+            while let Some((state, slots)) = stack.pop() {
+                if !visited.insert(state) {
+                    continue;
+                }
+
+                let mut targets: Vec<StateIndex> = self
+                    .transition_matrix
+                    .targets(state, &Label::Epsilon)
+                    .collect();
+                targets.sort();
+                for target in targets.into_iter().rev() {
+                    let mut slots = slots.clone();
+                    for tag in
+                        self.transition_matrix.tags_between(state, target)
+                    {
+                        slots[tag.slot()] = Some(position);
+                    }
+                    stack.push((target, slots));
+                }
+                closure.push((state, slots));
+            }
+
+            closure
+        }
+
+        fn add_tag(
+            &mut self,
+            from_state: StateIndex,
+            to_state: StateIndex,
+            tag: Tag,
+        ) {
+            self.transition_matrix.add_tag(
+                &self.states[from_state],
+                &self.states[to_state],
+                tag,
+            );
+        }
+
+        fn accepts_any(&self, states: &HashSet<StateIndex>) -> bool {
+            states.iter().any(|&state| self.states[state].is_final)
+        }
+
+        fn end_states(&self) -> impl Iterator<Item = &State> {
+            self.states.iter().filter(|state| state.is_final)
+        }
+    }
+
+    /// Returns a shortest string over `alphabet` matched by exactly one of
+    /// the patterns `a` and `b`, or `None` if they are equivalent.
+    pub fn regex_difference_witness(
+        a: &str,
+        b: &str,
+        alphabet: &[char],
+    ) -> Result<Option<String>, AlphabetError> {
+        let a = Automaton::from_regex_over(a, alphabet)?;
+        let b = Automaton::from_regex_over(b, alphabet)?;
+
+        Ok(a.difference_witness(&b, alphabet))
+    }
+
+    /// Reports whether the patterns `a` and `b` match the same strings over
+    /// `alphabet`. Patterns are read by `from_regex`, so they are literal
+    /// apart from `(?i)` and comments; automata built with operators such
+    /// as `union` and `kleene_closure` are compared with
+    /// `Automaton::is_equivalent`.
+    pub fn regex_equivalent(
+        a: &str,
+        b: &str,
+        alphabet: &[char],
+    ) -> Result<bool, RegexError> {
+        match regex_difference_witness(a, b, alphabet) {
+            Ok(witness) => Ok(witness.is_none()),
+            Err(error) => Err(RegexError::Alphabet(error)),
+        }
+    }
+
+    pub type NonTerminal<'a> = &'a str;
+
+    pub enum Production<'a> {
+        /// `A -> aB`
+        Step(&'a str, NonTerminal<'a>),
+        /// `A -> a`
+        Terminal(&'a str),
+        /// `A -> ε`
+        Empty,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum GrammarError {
+        NoRules,
+        UndefinedNonTerminal(String),
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum AlphabetError {
+        SymbolOutsideAlphabet(char),
+    }
+
+    /// Why two patterns could not be compared.
+    #[derive(Debug, PartialEq)]
+    pub enum RegexError {
+        Alphabet(AlphabetError),
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum EscapeError {
+        /// A `\x` or `\u` escape at this byte offset lacks its hex digits
+        /// or braces.
+        Malformed(usize),
+        /// A surrogate or a value beyond `U+10FFFF`.
+        InvalidCodePoint(u32),
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct Nondeterminizable {
+        pub max_states: usize,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct VerificationError {
+        pub rejected_positives: Vec<String>,
+        pub accepted_negatives: Vec<String>,
+    }
+
+    /// The label of an NFA transition. Epsilon is a variant of its own so
+    /// that it can never collide with an input symbol such as `ε`.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Label {
+        Epsilon,
+        Symbol(Symbol),
+    }
+
+    // A label as seen by the matrix rows, so that they can be queried with
+    // a borrowed `Some(&str)` rather than an owned `Label::Symbol`; `None`
+    // stands for epsilon.
+    trait LabelKey {
+        fn key(&self) -> Option<&str>;
+    }
+
+    impl LabelKey for Label {
+        fn key(&self) -> Option<&str> {
+            match self {
+                Label::Epsilon => None,
+                Label::Symbol(symbol) => Some(symbol),
+            }
+        }
+    }
+
+    impl LabelKey for Option<&str> {
+        fn key(&self) -> Option<&str> {
+            *self
+        }
+    }
+
+    impl<'a> Borrow<dyn LabelKey + 'a> for Label {
+        fn borrow(&self) -> &(dyn LabelKey + 'a) {
+            self
+        }
+    }
+
+    impl Hash for dyn LabelKey + '_ {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.key().hash(state);
+        }
+    }
+
+    impl PartialEq for dyn LabelKey + '_ {
+        fn eq(&self, other: &Self) -> bool {
+            self.key() == other.key()
+        }
+    }
+
+    impl Eq for dyn LabelKey + '_ {}
+
+    // Must agree with the hash of the borrowed `dyn LabelKey`.
+    impl Hash for Label {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.key().hash(state);
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct State {
+        number: StateIndex,
+        name: String,
+        is_final: bool,
+    }
+
+    impl State {
+        pub fn new(number: StateIndex, is_final: bool) -> State {
+            State {
+                number,
+                name: "s".to_string() + number.to_string().as_str(),
+                is_final,
+            }
+        }
+
+        pub fn number(&self) -> StateIndex {
+            self.number
+        }
+
+        pub fn is_final(&self) -> bool {
+            self.is_final
+        }
+    }
+
+    pub struct Transition<'a> {
+        from_state: &'a State,
+        to_state: &'a State,
+        label: Label,
+    }
+
+    impl<'a> Transition<'a> {
+        pub fn new(
+            from_state: &'a State,
+            to_state: &'a State,
+            label: Label,
+        ) -> Transition<'a> {
+            Transition {
+                from_state,
+                to_state,
+                label,
+            }
+        }
+
+        pub fn from_state(&self) -> &State {
+            self.from_state
+        }
+
+        pub fn to_state(&self) -> &State {
+            self.to_state
+        }
+
+        pub fn label(&self) -> &Label {
+            &self.label
+        }
+
+        /// Returns `None` for epsilon transitions.
+        pub fn symbol(&self) -> Option<&str> {
+            match &self.label {
+                Label::Epsilon => None,
+                Label::Symbol(symbol) => Some(symbol),
+            }
+        }
+
+        /// Renders the transition as `(from->to,label)`, where a symbol is
+        /// quoted and epsilon is a bare `ε`, so the two never coincide.
+        pub fn to_str(&self) -> String {
+            let label = match self.symbol() {
+                None => EPSILON.to_string(),
+                Some(symbol) => format!("{:?}", symbol),
+            };
+
+            format!(
+                "({}->{},{})",
+                &self.from_state.name, &self.to_state.name, label
+            )
+        }
+    }
+
+    /// Marks where capture group `n` opens or closes; carried by epsilon
+    /// transitions.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub enum Tag {
+        Open(usize),
+        Close(usize),
+    }
+
+    impl Tag {
+        pub fn group(&self) -> usize {
+            match self {
+                Tag::Open(group) | Tag::Close(group) => *group,
+            }
+        }
+
+        // Captures keep an open and a close position per group.
+        fn slot(&self) -> usize {
+            match self {
+                Tag::Open(group) => 2 * group,
+                Tag::Close(group) => 2 * group + 1,
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct TransitionMatrix {
+        matrix: Vec<HashMap<Label, HashSet<StateIndex>>>,
+        tags: HashMap<(StateIndex, StateIndex), Vec<Tag>>,
+    }
+
+    impl TransitionMatrix {
+        pub fn new() -> TransitionMatrix {
+            TransitionMatrix::with_capacity(0)
+        }
+
+        pub fn with_capacity(states: usize) -> TransitionMatrix {
+            TransitionMatrix {
+                matrix: Vec::with_capacity(states),
+                tags: HashMap::new(),
+            }
+        }
 
         pub fn is_valid(
             &self,
@@ -303,9 +4482,10 @@ mod nfa {
             to_state: &State,
             symbol: &str,
         ) -> bool {
-            self.matrix.contains(
-                Transition::new(from_state, to_state, symbol).to_str().as_str(),
-            )
+            self.matrix
+                .get(from_state.number)
+                .and_then(|row| row.get(&Some(symbol) as &dyn LabelKey))
+                .is_some_and(|targets| targets.contains(&to_state.number))
         }
 
         pub fn add_transition(
@@ -314,11 +4494,857 @@ mod nfa {
             to_state: &State,
             symbol: &str,
         ) {
-            self.matrix.insert(
-                Transition::new(from_state, to_state, symbol)
-                    .to_str()
-                    .to_string(),
+            self.add(from_state, to_state, Label::Symbol(symbol.to_string()));
+        }
+
+        pub fn add_epsilon_transition(
+            &mut self,
+            from_state: &State,
+            to_state: &State,
+        ) {
+            self.add(from_state, to_state, Label::Epsilon);
+        }
+
+        /// Tags the epsilon transition from `from_state` to `to_state`.
+        pub fn add_tag(
+            &mut self,
+            from_state: &State,
+            to_state: &State,
+            tag: Tag,
+        ) {
+            self.tags
+                .entry((from_state.number, to_state.number))
+                .or_default()
+                .push(tag);
+        }
+
+        fn tags_between(
+            &self,
+            from_state: StateIndex,
+            to_state: StateIndex,
+        ) -> &[Tag] {
+            self.tags
+                .get(&(from_state, to_state))
+                .map_or(&[], |tags| tags.as_slice())
+        }
+
+        fn add(&mut self, from_state: &State, to_state: &State, label: Label) {
+            if from_state.number >= self.matrix.len() {
+                self.matrix.resize(from_state.number + 1, HashMap::new())
+            }
+            self.matrix[from_state.number]
+                .entry(label)
+                .or_default()
+                .insert(to_state.number);
+        }
+
+        fn targets<'a>(
+            &'a self,
+            from_state: StateIndex,
+            label: &dyn LabelKey,
+        ) -> impl Iterator<Item = StateIndex> + 'a {
+            self.matrix
+                .get(from_state)
+                .and_then(|row| row.get(label))
+                .into_iter()
+                .flatten()
+                .copied()
+        }
+
+        fn labels(
+            &self,
+            from_state: StateIndex,
+        ) -> impl Iterator<Item = &Label> {
+            self.matrix
+                .get(from_state)
+                .into_iter()
+                .flat_map(|row| row.keys())
+        }
+
+        fn iter(
+            &self,
+        ) -> impl Iterator<Item = (StateIndex, &Label, StateIndex)> {
+            self.matrix
+                .iter()
+                .enumerate()
+                .flat_map(|(from_state, row)| {
+                    row.iter().flat_map(move |(label, targets)| {
+                        targets
+                            .iter()
+                            .map(move |&to_state| (from_state, label, to_state))
+                    })
+                })
+        }
+    }
+
+    impl Default for TransitionMatrix {
+        fn default() -> TransitionMatrix {
+            TransitionMatrix::new()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn symbols(automaton: &Automaton) -> HashSet<String> {
+            automaton
+                .transitions()
+                .filter_map(|transition| {
+                    transition.symbol().map(str::to_string)
+                })
+                .collect()
+        }
+
+        #[test]
+        fn test_from_regex_implicit_concatenation() {
+            let automaton = Automaton::from_regex("ab");
+            assert_eq!(
+                symbols(&automaton),
+                HashSet::from(["a".to_string(), "b".to_string()])
+            );
+        }
+
+        #[test]
+        fn test_from_regex_explicit_concatenation() {
+            let automaton = Automaton::from_regex_explicit("ab", '.');
+            assert_eq!(symbols(&automaton), HashSet::from(["ab".to_string()]));
+
+            let automaton = Automaton::from_regex_explicit("ab.c", '.');
+            assert_eq!(
+                symbols(&automaton),
+                HashSet::from(["ab".to_string(), "c".to_string()])
+            );
+        }
+
+        #[test]
+        fn test_from_regex_explicit_space_operator() {
+            let automaton = Automaton::from_regex_explicit("if x", ' ');
+            assert_eq!(
+                symbols(&automaton),
+                HashSet::from(["if".to_string(), "x".to_string()])
+            );
+            assert_eq!(automaton.regex_str, "if x");
+        }
+
+        fn strings_up_to(length: usize) -> Vec<String> {
+            crate::automaton::tests::strings_over(&['a', 'b'], length)
+        }
+
+        fn assert_accepts_a_star(automaton: &Automaton) {
+            for input in strings_up_to(5) {
+                assert_eq!(
+                    automaton.matches(&input),
+                    !input.contains('b'),
+                    "{} on {:?}",
+                    automaton.regex_str,
+                    input
+                );
+            }
+        }
+
+        #[test]
+        fn test_nested_kleene_closure() {
+            let automaton =
+                Automaton::from_regex("a").kleene_closure().kleene_closure();
+
+            assert_eq!(automaton.regex_str, "(a*)*");
+            assert_accepts_a_star(&automaton);
+        }
+
+        #[test]
+        fn test_shuffle() {
+            let automaton = Automaton::from_regex("ab")
+                .shuffle(&Automaton::from_regex("cd"));
+
+            assert!(automaton.matches("acbd"));
+            assert!(automaton.matches("cdab"));
+            assert!(!automaton.matches("abdc"));
+            assert!(!automaton.matches("abc"));
+
+            let alphabet = ['a', 'b', 'c', 'd'];
+            for input in crate::automaton::tests::strings_over(&alphabet, 4) {
+                let position = |symbol| input.find(symbol);
+                let expected = ["a", "b", "c", "d"]
+                    .iter()
+                    .all(|symbol| input.matches(symbol).count() == 1)
+                    && position("a") < position("b")
+                    && position("c") < position("d");
+                assert_eq!(automaton.matches(&input), expected, "{:?}", input);
+            }
+        }
+
+        #[test]
+        fn test_shuffle_with_empty_string() {
+            let a_star = Automaton::from_regex("a").kleene_closure();
+            let automaton = Automaton::from_regex("").shuffle(&a_star);
+
+            assert!(automaton.matches(""));
+            assert!(automaton.matches("aaa"));
+            assert!(!automaton.matches("b"));
+        }
+
+        #[test]
+        fn test_optional_positive_closure() {
+            let automaton =
+                Automaton::from_regex("a").positive_closure().optional();
+
+            assert_eq!(automaton.regex_str, "(a+)?");
+            assert_accepts_a_star(&automaton);
+        }
+
+        #[test]
+        fn test_positive_closure_of_optional() {
+            let automaton =
+                Automaton::from_regex("a").optional().positive_closure();
+
+            assert_eq!(automaton.regex_str, "(a?)+");
+            assert_accepts_a_star(&automaton);
+        }
+
+        #[test]
+        fn test_optional_positive_closure_followed_by_symbol() {
+            let automaton = Automaton::from_regex("a")
+                .positive_closure()
+                .concatenate("b")
+                .optional();
+
+            for input in strings_up_to(5) {
+                let expected = input.is_empty()
+                    || (input.ends_with('b')
+                        && input.len() > 1
+                        && !input[..input.len() - 1].contains('b'));
+                assert_eq!(automaton.matches(&input), expected, "{:?}", input);
+            }
+        }
+
+        #[test]
+        fn test_epsilon_closure_terminates_on_cycles() {
+            let automaton = Automaton::from_regex("a")
+                .kleene_closure()
+                .kleene_closure()
+                .kleene_closure();
+            let start = HashSet::from([automaton.start_state.number]);
+
+            let closure = automaton.epsilon_closure(&start);
+            assert!(closure
+                .iter()
+                .any(|&state| automaton.states()[state].is_final()));
+        }
+
+        #[test]
+        fn test_step() {
+            let automaton = Automaton::from_regex("ab");
+            let is_final = |states: &HashSet<StateIndex>| {
+                states
+                    .iter()
+                    .any(|&state| automaton.states()[state].is_final())
+            };
+
+            let start = automaton.start_states();
+            assert!(!is_final(&start));
+
+            let after_a = automaton.step(&start, "a");
+            assert!(!after_a.is_empty());
+            assert!(!is_final(&after_a));
+
+            let after_b = automaton.step(&after_a, "b");
+            assert!(is_final(&after_b));
+
+            assert!(automaton.step(&after_b, "b").is_empty());
+            assert!(automaton.step(&start, "b").is_empty());
+        }
+
+        #[test]
+        fn test_find() {
+            let automaton = Automaton::from_regex("ab");
+            assert_eq!(automaton.find("xxaby"), Some(Span::new(2, 4)));
+            assert_eq!(automaton.find("xxa"), None);
+
+            let automaton = Automaton::from_regex("a").positive_closure();
+            assert_eq!(automaton.find("bbaaab"), Some(Span::new(2, 5)));
+        }
+
+        #[test]
+        fn test_find_agrees_with_dfa() {
+            let nfa = Automaton::from_regex("ab");
+            let mut dfa = crate::automaton::Automaton::new();
+            let start = crate::automaton::State::new(0, false, false);
+            let first = crate::automaton::State::new(1, false, false);
+            let second = crate::automaton::State::new(2, true, false);
+            dfa.add_transition(start, first, "a");
+            dfa.add_transition(first, second, "b");
+
+            for haystack in ["xxaby", "ab", "aab", "ba", "", "éab"] {
+                assert_eq!(
+                    nfa.find(haystack),
+                    dfa.find(haystack),
+                    "{}",
+                    haystack
+                );
+            }
+        }
+
+        #[test]
+        fn test_nested_star_has_no_epsilon_self_loops() {
+            let automaton =
+                Automaton::from_regex("a").kleene_closure().kleene_closure();
+
+            assert_eq!(automaton.check_no_epsilon_self_loops(), Ok(()));
+            assert_accepts_a_star(&automaton);
+        }
+
+        #[test]
+        fn test_star_of_empty_union_has_no_epsilon_self_loops() {
+            let automaton =
+                Automaton::from_regex("").union("").kleene_closure();
+
+            assert_eq!(automaton.regex_str, "(|)*");
+            assert_eq!(automaton.check_no_epsilon_self_loops(), Ok(()));
+            for input in strings_up_to(3) {
+                assert_eq!(automaton.matches(&input), input.is_empty());
+            }
+        }
+
+        #[test]
+        fn test_check_no_epsilon_self_loops() {
+            let mut automaton = Automaton::from_regex("a");
+            let start = automaton.start_state.clone();
+            automaton.add_epsilon_transition(&start, &start);
+
+            assert_eq!(automaton.check_no_epsilon_self_loops(), Err(vec![0]));
+            assert!(automaton.matches("a"));
+        }
+
+        #[test]
+        fn test_from_right_linear() {
+            let automaton = Automaton::from_right_linear(&[
+                ("S", Production::Step("a", "S")),
+                ("S", Production::Terminal("b")),
+            ])
+            .unwrap();
+
+            for input in strings_up_to(4) {
+                let expected = input.ends_with('b')
+                    && !input[..input.len() - 1].contains('b');
+                assert_eq!(automaton.matches(&input), expected, "{:?}", input);
+            }
+        }
+
+        #[test]
+        fn test_from_right_linear_empty_production() {
+            let automaton = Automaton::from_right_linear(&[
+                ("S", Production::Step("a", "A")),
+                ("S", Production::Empty),
+                ("A", Production::Step("a", "S")),
+            ])
+            .unwrap();
+
+            assert!(automaton.matches(""));
+            assert!(!automaton.matches("a"));
+            assert!(automaton.matches("aa"));
+            assert!(!automaton.matches("aaa"));
+        }
+
+        #[test]
+        fn test_from_right_linear_errors() {
+            assert_eq!(
+                Automaton::from_right_linear(&[]).err(),
+                Some(GrammarError::NoRules)
+            );
+            assert_eq!(
+                Automaton::from_right_linear(&[(
+                    "S",
+                    Production::Step("a", "B")
+                )])
+                .err(),
+                Some(GrammarError::UndefinedNonTerminal("B".to_string()))
+            );
+        }
+
+        fn transition_strs(automaton: &Automaton) -> HashSet<String> {
+            automaton
+                .transitions()
+                .map(|transition| transition.to_str())
+                .collect()
+        }
+
+        #[test]
+        fn test_from_regex_skips_comments() {
+            let commented = Automaton::from_regex("a(?#the letter a)b");
+            let plain = Automaton::from_regex("ab");
+
+            assert_eq!(transition_strs(&commented), transition_strs(&plain));
+            assert_eq!(commented.regex_str, "ab");
+            assert!(commented.matches("ab"));
+        }
+
+        #[test]
+        fn test_from_regex_comment_content_is_not_syntax() {
+            let automaton = Automaton::from_regex("(?#a*|b()a");
+
+            assert_eq!(
+                transition_strs(&automaton),
+                transition_strs(&Automaton::from_regex("a"))
+            );
+        }
+
+        #[test]
+        fn test_from_regex_unterminated_comment() {
+            let automaton = Automaton::from_regex("ab(?#c");
+
+            assert!(automaton.matches("ab"));
+            assert!(!automaton.matches("ab(?#c"));
+        }
+
+        #[test]
+        fn test_from_regex_extended() {
+            let extended = Automaton::from_regex_extended(
+                "a b  # the first two letters\n\tc # and a third\n",
+            );
+            let compact = Automaton::from_regex("abc");
+
+            assert_eq!(transition_strs(&extended), transition_strs(&compact));
+            assert_eq!(extended.regex_str, "abc");
+        }
+
+        #[test]
+        fn test_from_regex_extended_literal_whitespace() {
+            let automaton = Automaton::from_regex_extended("a\\ b \\# [ ]");
+
+            assert_eq!(
+                transition_strs(&automaton),
+                transition_strs(&Automaton::from_regex("a b#[ ]"))
+            );
+        }
+
+        #[test]
+        fn test_case_insensitive_cyrillic() {
+            let automaton = Automaton::from_regex("(?i)привет");
+
+            assert_eq!(automaton.regex_str, "(?i)привет");
+            for input in ["привет", "ПРИВЕТ", "ПрИвЕт"] {
+                assert!(automaton.matches(input), "{}", input);
+            }
+            assert!(!automaton.matches("пока"));
+        }
+
+        #[test]
+        fn test_case_insensitive_greek() {
+            let automaton = Automaton::from_regex("(?i)λόγος");
+
+            for input in ["λόγος", "ΛΌΓΟΣ", "λόγοσ", "ΛΌΓΟς"]
+            {
+                assert!(automaton.matches(input), "{}", input);
+            }
+            assert!(!automaton.matches("λογος"));
+        }
+
+        #[test]
+        fn test_case_insensitive_special_folds() {
+            let automaton = Automaton::from_regex("(?i)k");
+            assert!(automaton.matches("K"));
+            assert!(automaton.matches("\u{212A}"));
+
+            assert_eq!(case_variants('σ'), vec!['Σ', 'ς', 'σ']);
+            assert_eq!(case_variants('ß'), vec!['ß', 'ẞ']);
+        }
+
+        #[test]
+        fn test_case_sensitive_by_default() {
+            let automaton = Automaton::from_regex("привет");
+            assert!(!automaton.matches("ПРИВЕТ"));
+        }
+
+        #[test]
+        fn test_from_regex_escaped() {
+            let automaton = Automaton::from_regex_escaped("\\x41b").unwrap();
+            assert!(automaton.matches("Ab"));
+            assert!(!automaton.matches("\\x41b"));
+
+            let automaton =
+                Automaton::from_regex_escaped("\\u{1F600}").unwrap();
+            assert!(automaton.matches("😀"));
+            assert!(!automaton.matches("\\u{1F600}"));
+
+            let automaton =
+                Automaton::from_regex_escaped("\\\\x41\\n").unwrap();
+            assert!(automaton.matches("\\x41\\n"));
+        }
+
+        #[test]
+        fn test_from_regex_escaped_case_insensitive() {
+            let automaton = Automaton::from_regex_escaped("(?i)\\x61").unwrap();
+
+            assert!(automaton.matches("a"));
+            assert!(automaton.matches("A"));
+        }
+
+        #[test]
+        fn test_from_regex_escaped_errors() {
+            for (regex_str, error) in [
+                ("\\u{110000}", EscapeError::InvalidCodePoint(0x110000)),
+                ("\\u{D800}", EscapeError::InvalidCodePoint(0xD800)),
+                ("a\\x4", EscapeError::Malformed(1)),
+                ("\\xg1", EscapeError::Malformed(0)),
+                ("\\u{}", EscapeError::Malformed(0)),
+                ("\\u{+41}", EscapeError::Malformed(0)),
+                ("\\u{41", EscapeError::Malformed(0)),
+                ("\\u{1000000}", EscapeError::Malformed(0)),
+            ] {
+                assert_eq!(
+                    Automaton::from_regex_escaped(regex_str).err(),
+                    Some(error),
+                    "{}",
+                    regex_str
+                );
+            }
+        }
+
+        #[test]
+        fn test_from_regex_over() {
+            let alphabet: Vec<char> = ('a'..='e').collect();
+
+            let automaton =
+                Automaton::from_regex_over("bad", &alphabet).unwrap();
+            assert!(automaton.matches("bad"));
+
+            assert_eq!(
+                Automaton::from_regex_over("daze", &alphabet).err(),
+                Some(AlphabetError::SymbolOutsideAlphabet('z'))
+            );
+        }
+
+        #[test]
+        fn test_from_regex_over_ignores_syntax() {
+            let alphabet: Vec<char> = ('a'..='e').collect();
+
+            assert!(
+                Automaton::from_regex_over("(?i)a(?#note)b", &alphabet).is_ok()
+            );
+        }
+
+        #[test]
+        fn test_difference_witness() {
+            let a = Automaton::from_regex("a");
+            let alphabet = ['a', 'b'];
+
+            assert_eq!(
+                a.kleene_closure()
+                    .difference_witness(&a.positive_closure(), &alphabet),
+                Some(String::new())
+            );
+            assert_eq!(
+                a.positive_closure()
+                    .difference_witness(&a.concatenate("a"), &alphabet),
+                Some("a".to_string())
+            );
+            assert_eq!(
+                a.positive_closure().optional().difference_witness(
+                    &a.kleene_closure().kleene_closure(),
+                    &alphabet
+                ),
+                None
+            );
+        }
+
+        #[test]
+        fn test_regex_difference_witness() {
+            let alphabet = ['a', 'b'];
+
+            assert_eq!(
+                regex_difference_witness("ab", "ab", &alphabet),
+                Ok(None)
+            );
+            assert_eq!(
+                regex_difference_witness("aba", "ab", &alphabet),
+                Ok(Some("ab".to_string()))
+            );
+            assert_eq!(
+                regex_difference_witness("ab", "ac", &alphabet),
+                Err(AlphabetError::SymbolOutsideAlphabet('c'))
+            );
+        }
+
+        #[test]
+        fn test_regex_equivalent() {
+            let alphabet = ['a', 'b', 'A', 'B'];
+
+            assert_eq!(
+                regex_equivalent("(?i)ab", "(?i)AB", &alphabet),
+                Ok(true)
+            );
+            assert_eq!(
+                regex_equivalent("a(?#note)b", "ab", &alphabet),
+                Ok(true)
+            );
+            assert_eq!(regex_equivalent("(?i)ab", "ab", &alphabet), Ok(false));
+            assert_eq!(regex_equivalent("ab", "aba", &alphabet), Ok(false));
+            assert_eq!(
+                regex_equivalent("ab", "ac", &alphabet),
+                Err(RegexError::Alphabet(
+                    AlphabetError::SymbolOutsideAlphabet('c')
+                ))
             );
         }
+
+        #[test]
+        fn test_is_equivalent() {
+            let alphabet = ['a', 'b'];
+            let a_or_b = Automaton::from_regex("a").union("b").kleene_closure();
+            let b_or_a = Automaton::from_regex("b").union("a").kleene_closure();
+            let a_star = Automaton::from_regex("a").kleene_closure();
+            let a_plus = Automaton::from_regex("a").positive_closure();
+
+            assert!(a_or_b.is_equivalent(&b_or_a, &alphabet));
+            assert!(a_star.is_equivalent(&a_star, &alphabet));
+            assert!(!a_star.is_equivalent(&a_plus, &alphabet));
+            assert!(!a_or_b.is_equivalent(&a_star, &alphabet));
+        }
+
+        #[test]
+        fn test_group_tags_survive_composition() {
+            let automaton = Automaton::from_regex("a")
+                .group(1)
+                .concatenate_with(&Automaton::from_regex("b").group(2));
+            assert_eq!(automaton.regex_str, "(a)(b)");
+
+            let symbol_edges: Vec<(StateIndex, String, StateIndex)> = automaton
+                .transitions()
+                .filter_map(|transition| {
+                    Some((
+                        transition.from_state().number,
+                        transition.symbol()?.to_string(),
+                        transition.to_state().number,
+                    ))
+                })
+                .collect();
+            let edge = |symbol: &str| {
+                symbol_edges
+                    .iter()
+                    .find(|edge| edge.1 == symbol)
+                    .map(|edge| (edge.0, edge.2))
+                    .unwrap()
+            };
+            let (a_from, a_to) = edge("a");
+            let (b_from, b_to) = edge("b");
+            let tagged = automaton.tagged_transitions();
+            let tag_edge = |tag: Tag| {
+                tagged
+                    .iter()
+                    .find(|edge| edge.1 == tag)
+                    .map(|edge| (edge.0, edge.2))
+                    .unwrap()
+            };
+
+            assert_eq!(tagged.len(), 4);
+            assert_eq!(tag_edge(Tag::Open(1)).1, a_from);
+            assert_eq!(tag_edge(Tag::Close(1)).0, a_to);
+            assert_eq!(tag_edge(Tag::Open(2)).1, b_from);
+            assert_eq!(tag_edge(Tag::Close(2)).0, b_to);
+            assert_eq!(tag_edge(Tag::Open(1)).0, automaton.start_state.number);
+        }
+
+        #[test]
+        fn test_captures() {
+            let automaton = Automaton::from_regex("a")
+                .group(1)
+                .concatenate_with(&Automaton::from_regex("b").group(2));
+
+            assert_eq!(
+                automaton.captures("ab"),
+                Some(vec![None, Some(Span::new(0, 1)), Some(Span::new(1, 2))])
+            );
+            assert_eq!(automaton.captures("a"), None);
+
+            let automaton = Automaton::from_regex("x")
+                .concatenate_with(
+                    &Automaton::from_regex("a").positive_closure().group(0),
+                )
+                .kleene_closure();
+            assert_eq!(
+                automaton.captures("xaaxa"),
+                Some(vec![Some(Span::new(4, 5))])
+            );
+            assert_eq!(automaton.captures(""), Some(vec![None]));
+        }
+
+        #[test]
+        fn test_as_literal() {
+            let literal = |automaton: Automaton| automaton.as_literal();
+
+            assert_eq!(
+                literal(Automaton::from_regex("abc")),
+                Some("abc".into())
+            );
+            assert_eq!(literal(Automaton::from_regex("")), Some(String::new()));
+            // `*` is not an operator in `from_regex` patterns.
+            assert_eq!(
+                literal(Automaton::from_regex("a*b")),
+                Some("a*b".into())
+            );
+            assert_eq!(
+                literal(Automaton::from_regex("ab(?#note)c")),
+                Some("abc".into())
+            );
+            assert_eq!(
+                literal(Automaton::from_regex("a").union("a")),
+                Some("a".into())
+            );
+
+            assert_eq!(
+                literal(Automaton::from_regex("a").kleene_closure()),
+                None
+            );
+            assert_eq!(literal(Automaton::from_regex("a").optional()), None);
+            assert_eq!(literal(Automaton::from_regex("a").union("b")), None);
+            assert_eq!(literal(Automaton::from_regex("(?i)a")), None);
+            assert_eq!(
+                literal(Automaton::from_regex("(?i)1")),
+                Some("1".into())
+            );
+        }
+
+        #[test]
+        fn test_to_dfa_bounded() {
+            let automaton = Automaton::from_regex("a")
+                .union("b")
+                .kleene_closure()
+                .concatenate("ab");
+
+            let dfa = automaton.to_dfa_bounded(4).unwrap();
+            for input in strings_up_to(6) {
+                assert_eq!(
+                    dfa.consume(&input),
+                    automaton.matches(&input),
+                    "{:?}",
+                    input
+                );
+            }
+            assert_eq!(
+                automaton.to_dfa_bounded(3).err(),
+                Some(Nondeterminizable { max_states: 3 })
+            );
+        }
+
+        #[test]
+        fn test_to_dfa_bounded_zero() {
+            let automaton = Automaton::from_regex("a");
+
+            assert_eq!(
+                automaton.to_dfa_bounded(0).err(),
+                Some(Nondeterminizable { max_states: 0 })
+            );
+            assert!(automaton.to_dfa_bounded(2).is_ok());
+        }
+
+        #[test]
+        fn test_to_dfa_bounded_exceeded() {
+            // (a|b)*a(a|b)(a|b)(a|b) needs a state per possible last four
+            // symbols, besides the start.
+            let a_or_b = Automaton::from_regex("a").union("b");
+            let mut automaton = a_or_b.kleene_closure().concatenate("a");
+            for _ in 0..3 {
+                automaton = automaton.concatenate_with(&a_or_b);
+            }
+
+            assert_eq!(
+                automaton.to_dfa_bounded(10).err(),
+                Some(Nondeterminizable { max_states: 10 })
+            );
+
+            let dfa = automaton.to_dfa_bounded(17).unwrap();
+            for input in strings_up_to(7) {
+                assert_eq!(
+                    dfa.consume(&input),
+                    automaton.matches(&input),
+                    "{:?}",
+                    input
+                );
+            }
+        }
+
+        #[test]
+        fn test_compile_and_verify() {
+            let automaton =
+                Automaton::compile_and_verify("ab", &["ab"], &["", "a", "abb"])
+                    .unwrap();
+            assert!(automaton.matches("ab"));
+
+            assert_eq!(
+                Automaton::compile_and_verify("ab", &["ab", "ba"], &["ab"])
+                    .err(),
+                Some(VerificationError {
+                    rejected_positives: vec!["ba".to_string()],
+                    accepted_negatives: vec!["ab".to_string()],
+                })
+            );
+        }
+
+        #[test]
+        fn test_epsilon_symbol_is_a_literal() {
+            let automaton = Automaton::from_regex("aεb");
+
+            assert!(automaton.matches("aεb"));
+            assert!(!automaton.matches("ab"));
+
+            let automaton = Automaton::from_regex("ε").kleene_closure();
+            assert!(automaton.matches(""));
+            assert!(automaton.matches("εε"));
+            assert!(!automaton.matches("a"));
+        }
+
+        #[test]
+        fn test_epsilon_transition_is_not_a_symbol() {
+            let automaton = Automaton::from_regex_explicit("a..b", '.');
+
+            assert!(automaton.matches("ab"));
+            assert!(!automaton.matches("aεb"));
+            assert!(automaton
+                .transitions()
+                .any(|transition| *transition.label() == Label::Epsilon));
+        }
+
+        #[test]
+        fn test_epsilon_transition_str() {
+            let symbol = Automaton::from_regex("ε");
+            let epsilon = Automaton::from_regex("");
+
+            assert_eq!(
+                transition_strs(&symbol),
+                HashSet::from(["(s0->s1,\"ε\")".to_string()])
+            );
+            assert_eq!(
+                transition_strs(&epsilon),
+                HashSet::from(["(s0->s1,ε)".to_string()])
+            );
+        }
+
+        #[test]
+        fn test_transition_counts() {
+            let automaton = Automaton::from_regex("a");
+            assert_eq!(automaton.transition_count(), 1);
+            assert_eq!(automaton.epsilon_count(), 0);
+
+            // A new final state, entered from `a` and looping back to it,
+            // and a new start state leading to `a` and to the final state.
+            let automaton = automaton.kleene_closure();
+            assert_eq!(automaton.transition_count(), 5);
+            assert_eq!(automaton.epsilon_count(), 4);
+
+            let automaton = Automaton::from_regex("ab").union("c");
+            assert_eq!(automaton.transition_count(), 8);
+            assert_eq!(automaton.epsilon_count(), 5);
+        }
+
+        #[test]
+        fn test_from_regex_long_pattern() {
+            let pattern = "ab".repeat(5_000);
+            let automaton = Automaton::from_regex(&pattern);
+
+            assert_eq!(automaton.states().len(), 2 * pattern.len());
+            assert!(automaton.matches(&pattern));
+            assert!(!automaton.matches(&pattern[1..]));
+        }
     }
 }
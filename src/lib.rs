@@ -1 +1,2 @@
 pub mod automaton;
+pub mod span;
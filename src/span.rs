@@ -0,0 +1,71 @@
+use std::ops::Range;
+
+/// A half-open range of byte offsets into a haystack.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn as_str<'a>(&self, haystack: &'a str) -> &'a str {
+        &haystack[self.range()]
+    }
+
+    /// The char offset of `start` in `haystack`.
+    pub fn char_start(&self, haystack: &str) -> usize {
+        haystack[..self.start].chars().count()
+    }
+
+    /// The char offset of `end` in `haystack`.
+    pub fn char_end(&self, haystack: &str) -> usize {
+        haystack[..self.end].chars().count()
+    }
+
+    pub fn char_len(&self, haystack: &str) -> usize {
+        self.as_str(haystack).chars().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span() {
+        let span = Span::new(2, 4);
+
+        assert_eq!(span.len(), 2);
+        assert!(!span.is_empty());
+        assert_eq!(span.range(), 2..4);
+        assert_eq!(span.as_str("xxaby"), "ab");
+        assert!(Span::new(3, 3).is_empty());
+    }
+
+    #[test]
+    fn test_span_char_offsets() {
+        let haystack = "éλab";
+        let span = Span::new(4, 6);
+
+        assert_eq!(span.as_str(haystack), "ab");
+        assert_eq!(span.char_start(haystack), 2);
+        assert_eq!(span.char_end(haystack), 4);
+        assert_eq!(span.char_len(haystack), 2);
+    }
+}